@@ -1,15 +1,56 @@
 #![allow(non_snake_case)]
+// The renderer modules are shared with the binary, which runs their unit tests
+#![cfg_attr(test, allow(dead_code, unused_imports))]
 
 use criterion::{criterion_group, criterion_main, Criterion};
 use image::{ImageBuffer, Rgb};
 
 #[path = "../src/vec_ops.rs"]
 mod vec_ops;
+#[path = "../src/sampling.rs"]
+mod sampling;
+#[path = "../src/sampler.rs"]
+mod sampler;
+#[path = "../src/bsdf.rs"]
+mod bsdf;
+#[path = "../src/intersection.rs"]
+mod intersection;
+#[path = "../src/light.rs"]
+mod light;
+#[path = "../src/integrator.rs"]
+mod integrator;
+#[path = "../src/aov.rs"]
+mod aov;
+#[path = "../src/film.rs"]
+mod film;
+#[path = "../src/accumulator.rs"]
+mod accumulator;
+#[path = "../src/tonemap.rs"]
+mod tonemap;
+#[path = "../src/tiles.rs"]
+mod tiles;
+#[path = "../src/material.rs"]
+mod material;
+#[path = "../src/texture.rs"]
+mod texture;
+#[path = "../src/world.rs"]
+mod world;
+#[path = "../src/loader.rs"]
+mod loader;
+#[path = "../src/scene_file.rs"]
+mod scene_file;
+#[path = "../src/distortion.rs"]
+mod distortion;
+#[path = "../src/projection.rs"]
+mod projection;
 #[path = "../src/camera.rs"]
 mod camera;
+#[path = "../src/calibration.rs"]
+mod calibration;
 #[path = "../src/renderer.rs"]
 mod renderer;
 
+use crate::integrator::IntegratorKind;
 use crate::renderer::{CreateEguiColorImageFromImageBuffer, Renderer};
 
 fn bench_Raygen(c: &mut Criterion) {
@@ -21,6 +62,9 @@ fn bench_Raygen(c: &mut Criterion) {
     c.bench_function("GetRays 640x480", |x| x.iter(|| { renderer.camera.getRays(); }));
     c.bench_function("RenderImageBuffer 640x480", |x| x.iter(|| { renderer.renderImageBuffer(); }));
     c.bench_function("ImageToEgui 640x480", |x| x.iter(|| { CreateEguiColorImageFromImageBuffer(&imageBuffer); }));
+    renderer.setIntegrator(IntegratorKind::PathTracing);
+    c.bench_function("PathTrace 640x480 1spp", |x| x.iter(|| { renderer.renderHdrImageBuffer(1); }));
+    renderer.setIntegrator(IntegratorKind::Normals);
 
     let imageBuffer = ImageBuffer::<Rgb<u8>, Vec<u8>>::new(800, 600);
     renderer.camera.resize(800.0, 600.0);
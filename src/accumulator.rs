@@ -0,0 +1,43 @@
+use image::Rgb32FImage;
+
+use crate::film::Film;
+
+// Running filter weighted sum of sample passes, resolved on demand for progressive display
+pub struct Accumulator {
+    sum: Film,
+    passes: u32,
+}
+
+impl Accumulator {
+    pub fn new() -> Self {
+        Self {
+            sum: Film::new(0, 0),
+            passes: 0,
+        }
+    }
+
+    pub fn passes(&self) -> u32 {
+        self.passes
+    }
+
+    // Drops the accumulated passes, call on every change that invalidates them
+    pub fn reset(&mut self) {
+        self.passes = 0;
+    }
+
+    pub fn addPass(&mut self, pass: &Film) {
+        if self.passes == 0 || (self.sum.width, self.sum.height) != (pass.width, pass.height) {
+            self.sum = pass.clone();
+            self.passes = 1;
+            return;
+        }
+
+        self.sum.merge(pass);
+        self.passes += 1;
+    }
+
+    // Weights travel with the sums, so this is the reconstruction over all samples of all passes
+    pub fn average(&self) -> Rgb32FImage {
+        self.sum.image()
+    }
+}
@@ -0,0 +1,200 @@
+use nalgebra::{Vector2, Vector3};
+
+use crate::intersection::{Ray, MAX_PASS_THROUGH};
+use crate::world::World;
+
+// Geometry and primitive id of pixels where the camera ray leaves the scene
+pub const NO_OBJECT: u32 = u32::MAX;
+
+// Surfaces this transparent are looked through, like the path tracer does on average
+const OPACITY_CUTOFF: f32 = 0.5;
+
+// First hit quantities of a camera ray
+#[derive(Clone, Copy, Debug)]
+pub struct AovSample {
+    pub depth: f32,
+    pub position: Vector3<f32>,
+    pub normal: Vector3<f32>,
+    pub albedo: Vector3<f32>,
+    pub geometryId: u32,
+    pub primitiveId: u32,
+    pub barycentrics: Vector2<f32>,
+}
+
+impl AovSample {
+    pub fn background() -> Self {
+        Self {
+            depth: f32::INFINITY,
+            position: Vector3::zeros(),
+            normal: Vector3::zeros(),
+            albedo: Vector3::zeros(),
+            geometryId: NO_OBJECT,
+            primitiveId: NO_OBJECT,
+            barycentrics: Vector2::zeros(),
+        }
+    }
+}
+
+// One output variable for every pixel, stored row-major like the beauty image
+#[derive(Clone, Debug)]
+pub struct AovBuffer<T> {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<T>,
+}
+
+impl<T: Copy> AovBuffer<T> {
+    pub fn new(width: u32, height: u32, value: T) -> Self {
+        Self { width, height, data: vec![value; (width * height) as usize] }
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, value: T) {
+        self.data[(y * self.width + x) as usize] = value;
+    }
+}
+
+// Auxiliary render outputs for compositing, denoising and perception tooling
+pub struct AovBuffers {
+    pub depth: AovBuffer<f32>,                  // distance along the camera axis, infinite on background
+    pub position: AovBuffer<Vector3<f32>>,      // world space hit point
+    pub normal: AovBuffer<Vector3<f32>>,        // world space shading normal facing the camera
+    pub albedo: AovBuffer<Vector3<f32>>,        // surface color without lighting
+    pub geometryId: AovBuffer<u32>,             // embree geometry id, NO_OBJECT on background
+    pub primitiveId: AovBuffer<u32>,            // triangle index within the geometry
+    pub barycentrics: AovBuffer<Vector2<f32>>,  // weights of the second and third triangle vertex
+}
+
+impl AovBuffers {
+    pub fn new(width: u32, height: u32) -> Self {
+        let background = AovSample::background();
+        Self {
+            depth: AovBuffer::new(width, height, background.depth),
+            position: AovBuffer::new(width, height, background.position),
+            normal: AovBuffer::new(width, height, background.normal),
+            albedo: AovBuffer::new(width, height, background.albedo),
+            geometryId: AovBuffer::new(width, height, background.geometryId),
+            primitiveId: AovBuffer::new(width, height, background.primitiveId),
+            barycentrics: AovBuffer::new(width, height, background.barycentrics),
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.depth.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.depth.height
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, sample: &AovSample) {
+        self.depth.set(x, y, sample.depth);
+        self.position.set(x, y, sample.position);
+        self.normal.set(x, y, sample.normal);
+        self.albedo.set(x, y, sample.albedo);
+        self.geometryId.set(x, y, sample.geometryId);
+        self.primitiveId.set(x, y, sample.primitiveId);
+        self.barycentrics.set(x, y, sample.barycentrics);
+    }
+}
+
+// Traces the ray to the first opaque surface; depth is measured along the camera forward axis
+pub fn ShadeAov(world: &World, ray: &Ray, forward: &Vector3<f32>) -> AovSample {
+    let mut origin = ray.origin;
+    let coneWidth = |distance: f32| distance * ray.spreadAngle;
+
+    for _ in 0..MAX_PASS_THROUGH {
+        let hit = match world.intersect(&origin, &ray.direction) {
+            Some(hit) => hit,
+            None => break,
+        };
+        // From the camera rather than summed hit distances, which miss the offsets of spawned rays
+        let traveled = (hit.position - ray.origin).norm();
+
+        let material = world.material(hit.geometryId);
+        let uvFootprint = hit.uvFootprint(coneWidth(traveled), &ray.direction);
+        if material.opacityAt(&hit, uvFootprint) < OPACITY_CUTOFF {
+            origin = hit.spawnOrigin(&ray.direction);
+            continue;
+        }
+
+        // Glass has no color of its own, report it as white like denoisers expect
+        let albedo = if material.transmission > 0.5 { Vector3::new(1.0, 1.0, 1.0) } else { material.diffuseAt(&hit, uvFootprint) };
+
+        return AovSample {
+            depth: traveled * ray.direction.dot(forward),
+            position: hit.position,
+            normal: material.perturbNormal(&hit, &hit.shadingNormal, uvFootprint),
+            albedo,
+            geometryId: hit.geometryId,
+            primitiveId: hit.primitiveId,
+            barycentrics: Vector2::new(hit.u, hit.v),
+        };
+    }
+
+    AovSample::background()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Material;
+    use crate::world::TriangleMesh;
+
+    // Unit sphere five units down the -z forward axis, optionally behind a quad at z = -2
+    fn Scene(quadOpacity: Option<f32>) -> (World, u32) {
+        let mut world = World::new();
+        let red = world.addMaterial(Material { diffuse: Vector3::new(0.8, 0.1, 0.1), ..Material::default() });
+        let sphere = world.addSphere((0.0, 0.0, -5.0), 1.0, red);
+        if let Some(opacity) = quadOpacity {
+            let positions = vec![
+                Vector3::new(-2.0, -2.0, -2.0),
+                Vector3::new(2.0, -2.0, -2.0),
+                Vector3::new(2.0, 2.0, -2.0),
+                Vector3::new(-2.0, 2.0, -2.0),
+            ];
+            let cutOut = world.addMaterial(Material { opacity, ..Material::default() });
+            world.addTriangleMesh(TriangleMesh::new(positions, vec![(0, 1, 2), (0, 2, 3)]), cutOut);
+        }
+        world.commit();
+        (world, sphere)
+    }
+
+    #[test]
+    fn DepthIsMeasuredAlongForward() {
+        let (world, sphere) = Scene(None);
+        let forward = -Vector3::z();
+        let direction = Vector3::new(0.15, 0.05, -1.0).normalize();
+        let sample = ShadeAov(&world, &Ray::new(Vector3::zeros(), direction, 0.0), &forward);
+
+        // Nearest root of |t d - c|^2 = 1
+        let center = Vector3::new(0.0, 0.0, -5.0);
+        let b = direction.dot(&center);
+        let distance = b - (b * b - center.norm_squared() + 1.0).sqrt();
+
+        assert_eq!(sample.geometryId, sphere);
+        assert!((sample.depth - distance * direction.dot(&forward)).abs() < 1e-4, "{} {}", sample.depth, distance);
+        assert!(sample.depth < distance);
+        assert!((sample.position - direction * distance).norm() < 1e-4);
+        assert!((sample.normal - (sample.position - center)).norm() < 1e-3);
+        assert_eq!(sample.albedo, Vector3::new(0.8, 0.1, 0.1));
+
+        let miss = ShadeAov(&world, &Ray::new(Vector3::zeros(), Vector3::z(), 0.0), &forward);
+        assert_eq!((miss.depth, miss.geometryId, miss.primitiveId), (f32::INFINITY, NO_OBJECT, NO_OBJECT));
+    }
+
+    #[test]
+    fn MostlyTransparentSurfacesAreLookedThrough() {
+        let forward = -Vector3::z();
+        let ray = Ray::new(Vector3::zeros(), forward, 0.0);
+
+        let (world, sphere) = Scene(Some(OPACITY_CUTOFF - 0.1));
+        let sample = ShadeAov(&world, &ray, &forward);
+        assert_eq!(sample.geometryId, sphere);
+        assert!((sample.depth - 4.0).abs() < 1e-4, "{}", sample.depth);
+
+        let (world, sphere) = Scene(Some(OPACITY_CUTOFF + 0.1));
+        let sample = ShadeAov(&world, &ray, &forward);
+        assert_ne!(sample.geometryId, sphere);
+        assert!((sample.depth - 2.0).abs() < 1e-4, "{}", sample.depth);
+    }
+}
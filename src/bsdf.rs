@@ -0,0 +1,628 @@
+use std::f32::consts::PI;
+
+use nalgebra::{Complex, Vector2, Vector3};
+
+use crate::sampling::SampleCosineHemisphere;
+
+// All directions are in the local shading frame, with the normal along +z and pointing away from
+// the surface. wo is the direction towards the viewer, wi the direction towards the light.
+
+pub struct BsdfSample {
+    pub direction: Vector3<f32>,    // wi
+    pub weight: Vector3<f32>,       // f * |cos(theta_i)| / pdf
+    pub pdf: f32,                   // solid angle density, 1 for specular events
+    pub isSpecular: bool,
+}
+
+pub trait Bsdf {
+    // f(wo, wi) without the cosine term, 0 for specular lobes
+    fn evaluate(&self, wo: &Vector3<f32>, wi: &Vector3<f32>) -> Vector3<f32>;
+
+    // Importance samples wi given two uniform numbers for the direction and one for lobe selection
+    fn sample(&self, wo: &Vector3<f32>, u: &Vector2<f32>, uc: f32) -> Option<BsdfSample>;
+
+    // Solid angle density of sample() producing wi, 0 for specular lobes
+    fn pdf(&self, wo: &Vector3<f32>, wi: &Vector3<f32>) -> f32;
+
+    // Purely specular BSDFs cannot be evaluated, only sampled
+    fn isSpecular(&self) -> bool {
+        false
+    }
+}
+
+fn CosTheta(w: &Vector3<f32>) -> f32 {
+    w.z
+}
+
+fn SameHemisphere(a: &Vector3<f32>, b: &Vector3<f32>) -> bool {
+    a.z * b.z > 0.0
+}
+
+fn Reflect(wo: &Vector3<f32>, normal: &Vector3<f32>) -> Vector3<f32> {
+    -wo + normal * (2.0 * wo.dot(normal))
+}
+
+// Refracts wo through the interface with the given normal, eta being the relative index of refraction
+// of the side the normal points away from. Returns wi and the index ratio it went through, None on
+// total internal reflection.
+fn Refract(wo: &Vector3<f32>, normal: &Vector3<f32>, eta: f32) -> Option<(Vector3<f32>, f32)> {
+    let mut cosThetaI = normal.dot(wo);
+    let (mut eta, mut normal) = (eta, *normal);
+    if cosThetaI < 0.0 {
+        eta = 1.0 / eta;
+        cosThetaI = -cosThetaI;
+        normal = -normal;
+    }
+
+    let sin2ThetaT = (1.0 - cosThetaI * cosThetaI).max(0.0) / (eta * eta);
+    if sin2ThetaT >= 1.0 {
+        return None;
+    }
+    let cosThetaT = (1.0 - sin2ThetaT).sqrt();
+    Some((-wo / eta + normal * (cosThetaI / eta - cosThetaT), eta))
+}
+
+// Unpolarized Fresnel reflectance of a dielectric interface, eta = n_transmitted / n_incident
+pub fn FresnelDielectric(cosThetaI: f32, eta: f32) -> f32 {
+    let (mut cosThetaI, mut eta) = (cosThetaI.clamp(-1.0, 1.0), eta);
+    if cosThetaI < 0.0 {
+        eta = 1.0 / eta;
+        cosThetaI = -cosThetaI;
+    }
+
+    let sin2ThetaT = (1.0 - cosThetaI * cosThetaI) / (eta * eta);
+    if sin2ThetaT >= 1.0 {
+        return 1.0;
+    }
+    let cosThetaT = (1.0 - sin2ThetaT).max(0.0).sqrt();
+
+    let parallel = (eta * cosThetaI - cosThetaT) / (eta * cosThetaI + cosThetaT);
+    let perpendicular = (cosThetaI - eta * cosThetaT) / (cosThetaI + eta * cosThetaT);
+    (parallel * parallel + perpendicular * perpendicular) / 2.0
+}
+
+// Fresnel reflectance of a conductor with complex index of refraction eta + i k
+pub fn FresnelComplex(cosThetaI: f32, eta: Complex<f32>) -> f32 {
+    let cosThetaI = cosThetaI.clamp(0.0, 1.0);
+    let sin2ThetaI = 1.0 - cosThetaI * cosThetaI;
+    let sin2ThetaT = Complex::new(sin2ThetaI, 0.0) / (eta * eta);
+    let cosThetaT = (Complex::new(1.0, 0.0) - sin2ThetaT).sqrt();
+
+    let parallel = (eta * cosThetaI - cosThetaT) / (eta * cosThetaI + cosThetaT);
+    let perpendicular = (Complex::new(cosThetaI, 0.0) - eta * cosThetaT) / (Complex::new(cosThetaI, 0.0) + eta * cosThetaT);
+    (parallel.norm_sqr() + perpendicular.norm_sqr()) / 2.0
+}
+
+// Hemispherical average of FresnelDielectric for diffuse light hitting the interface from the
+// inside of a medium with relative index eta > 1 (Egan and Hilgeman 1973)
+fn InternalDiffuseReflectance(eta: f32) -> f32 {
+    -1.4399 / (eta * eta) + 0.7099 / eta + 0.6681 + 0.0636 * eta
+}
+
+// Isotropic Trowbridge-Reitz (GGX) distribution with visible normal sampling, see "Sampling the
+// GGX Distribution of Visible Normals" (Heitz 2018)
+#[derive(Clone, Copy, Debug)]
+pub struct GgxDistribution {
+    pub alpha: f32,
+}
+
+impl GgxDistribution {
+    // Perceptually linear roughness in [0, 1] to alpha
+    pub fn fromRoughness(roughness: f32) -> Self {
+        Self { alpha: roughness.clamp(0.0, 1.0).powi(2) }
+    }
+
+    // Below this the distribution is treated as a perfect mirror
+    pub fn isSmooth(&self) -> bool {
+        self.alpha < 1e-3
+    }
+
+    pub fn D(&self, wm: &Vector3<f32>) -> f32 {
+        let cos2Theta = wm.z * wm.z;
+        if cos2Theta <= 0.0 {
+            return 0.0;
+        }
+        let tan2Theta = (1.0 - cos2Theta).max(0.0) / cos2Theta;
+        let alpha2 = self.alpha * self.alpha;
+        let e = 1.0 + tan2Theta / alpha2;
+        1.0 / (PI * alpha2 * cos2Theta * cos2Theta * e * e)
+    }
+
+    fn lambda(&self, w: &Vector3<f32>) -> f32 {
+        let cos2Theta = w.z * w.z;
+        if cos2Theta <= 0.0 {
+            return f32::INFINITY;
+        }
+        let tan2Theta = (1.0 - cos2Theta).max(0.0) / cos2Theta;
+        ((1.0 + self.alpha * self.alpha * tan2Theta).sqrt() - 1.0) / 2.0
+    }
+
+    pub fn G1(&self, w: &Vector3<f32>) -> f32 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    pub fn G(&self, wo: &Vector3<f32>, wi: &Vector3<f32>) -> f32 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // Density of visible normals seen from w
+    pub fn visiblePdf(&self, w: &Vector3<f32>, wm: &Vector3<f32>) -> f32 {
+        let cosTheta = CosTheta(w).abs();
+        if cosTheta <= 0.0 {
+            return 0.0;
+        }
+        self.G1(w) / cosTheta * self.D(wm) * w.dot(wm).abs()
+    }
+
+    pub fn sampleVisibleNormal(&self, w: &Vector3<f32>, u: &Vector2<f32>) -> Vector3<f32> {
+        // Stretch to the hemisphere configuration
+        let mut wh = Vector3::new(self.alpha * w.x, self.alpha * w.y, w.z).normalize();
+        if wh.z < 0.0 {
+            wh = -wh;
+        }
+
+        let t1 = if wh.z < 0.99999 { Vector3::z().cross(&wh).normalize() } else { Vector3::x() };
+        let t2 = wh.cross(&t1);
+
+        // Uniform disk sample, warped to the projected hemisphere
+        let radius = u.x.sqrt();
+        let phi = 2.0 * PI * u.y;
+        let px = radius * phi.cos();
+        let mut py = radius * phi.sin();
+        let h = (1.0 - px * px).max(0.0).sqrt();
+        let s = (1.0 + wh.z) / 2.0;
+        py = (1.0 - s) * h + s * py;
+        let pz = (1.0 - px * px - py * py).max(0.0).sqrt();
+
+        let nh = t1 * px + t2 * py + wh * pz;
+        Vector3::new(self.alpha * nh.x, self.alpha * nh.y, nh.z.max(1e-6)).normalize()
+    }
+}
+
+pub struct Lambertian {
+    pub albedo: Vector3<f32>,
+}
+
+impl Bsdf for Lambertian {
+    fn evaluate(&self, wo: &Vector3<f32>, wi: &Vector3<f32>) -> Vector3<f32> {
+        if !SameHemisphere(wo, wi) {
+            return Vector3::zeros();
+        }
+        self.albedo / PI
+    }
+
+    fn sample(&self, wo: &Vector3<f32>, u: &Vector2<f32>, _uc: f32) -> Option<BsdfSample> {
+        let mut wi = SampleCosineHemisphere(u);
+        if wo.z < 0.0 {
+            wi.z = -wi.z;
+        }
+        let pdf = CosTheta(&wi).abs() / PI;
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample { direction: wi, weight: self.albedo, pdf, isSpecular: false })
+    }
+
+    fn pdf(&self, wo: &Vector3<f32>, wi: &Vector3<f32>) -> f32 {
+        if SameHemisphere(wo, wi) { CosTheta(wi).abs() / PI } else { 0.0 }
+    }
+}
+
+// Metal with per channel complex index of refraction and a GGX microfacet surface
+pub struct Conductor {
+    pub eta: Vector3<f32>,
+    pub k: Vector3<f32>,
+    pub distribution: GgxDistribution,
+}
+
+impl Conductor {
+    // Conductor with the given reflectance at normal incidence, for base color workflows. With
+    // eta = 1 the reflectance k^2 / (4 + k^2) solves directly for k.
+    pub fn fromReflectance(reflectance: &Vector3<f32>, roughness: f32) -> Self {
+        let k = reflectance.map(|r| {
+            let r = r.clamp(0.0, 0.9999);
+            2.0 * (r / (1.0 - r)).sqrt()
+        });
+        Self { eta: Vector3::new(1.0, 1.0, 1.0), k, distribution: GgxDistribution::fromRoughness(roughness) }
+    }
+
+    fn fresnel(&self, cosTheta: f32) -> Vector3<f32> {
+        Vector3::from_fn(|channel, _| FresnelComplex(cosTheta, Complex::new(self.eta[channel], self.k[channel])))
+    }
+}
+
+impl Bsdf for Conductor {
+    fn evaluate(&self, wo: &Vector3<f32>, wi: &Vector3<f32>) -> Vector3<f32> {
+        if !SameHemisphere(wo, wi) || self.distribution.isSmooth() {
+            return Vector3::zeros();
+        }
+        let (cosThetaO, cosThetaI) = (CosTheta(wo).abs(), CosTheta(wi).abs());
+        let wm = match (wi + wo).try_normalize(1e-12) {
+            Some(wm) if cosThetaO > 0.0 && cosThetaI > 0.0 => wm,
+            _ => return Vector3::zeros(),
+        };
+
+        let fresnel = self.fresnel(wo.dot(&wm).abs());
+        fresnel * (self.distribution.D(&wm) * self.distribution.G(wo, wi) / (4.0 * cosThetaI * cosThetaO))
+    }
+
+    fn sample(&self, wo: &Vector3<f32>, u: &Vector2<f32>, _uc: f32) -> Option<BsdfSample> {
+        if self.distribution.isSmooth() {
+            let wi = Vector3::new(-wo.x, -wo.y, wo.z);
+            return Some(BsdfSample { direction: wi, weight: self.fresnel(CosTheta(&wi).abs()), pdf: 1.0, isSpecular: true });
+        }
+
+        if wo.z == 0.0 {
+            return None;
+        }
+        let wm = self.distribution.sampleVisibleNormal(wo, u);
+        let wi = Reflect(wo, &wm);
+        if !SameHemisphere(wo, &wi) {
+            return None;
+        }
+
+        let pdf = self.distribution.visiblePdf(wo, &wm) / (4.0 * wo.dot(&wm).abs());
+        if pdf <= 0.0 {
+            return None;
+        }
+        let weight = self.evaluate(wo, &wi) * CosTheta(&wi).abs() / pdf;
+        Some(BsdfSample { direction: wi, weight, pdf, isSpecular: false })
+    }
+
+    fn pdf(&self, wo: &Vector3<f32>, wi: &Vector3<f32>) -> f32 {
+        if !SameHemisphere(wo, wi) || self.distribution.isSmooth() {
+            return 0.0;
+        }
+        let wm = match (wi + wo).try_normalize(1e-12) {
+            Some(wm) => if wm.z < 0.0 { -wm } else { wm },
+            None => return 0.0,
+        };
+        self.distribution.visiblePdf(wo, &wm) / (4.0 * wo.dot(&wm).abs())
+    }
+
+    fn isSpecular(&self) -> bool {
+        self.distribution.isSmooth()
+    }
+}
+
+// Glass-like interface reflecting and transmitting according to Fresnel, smooth or with GGX
+// microfacets ("Microfacet Models for Refraction through Rough Surfaces", Walter et al. 2007).
+// eta is the index of the side below the surface relative to the side above it. Transmitted
+// radiance is scaled by 1 / eta^2 as it is compressed into the denser medium.
+pub struct Dielectric {
+    pub eta: f32,
+    pub distribution: GgxDistribution,
+}
+
+impl Dielectric {
+    // Microfacet normal of a reflection or refraction pair, on the +z side, and the index ratio
+    fn generalizedHalfVector(&self, wo: &Vector3<f32>, wi: &Vector3<f32>) -> Option<(Vector3<f32>, f32)> {
+        let (cosThetaO, cosThetaI) = (CosTheta(wo), CosTheta(wi));
+        if cosThetaO == 0.0 || cosThetaI == 0.0 {
+            return None;
+        }
+
+        let reflect = cosThetaO * cosThetaI > 0.0;
+        let etap = if reflect { 1.0 } else if cosThetaO > 0.0 { self.eta } else { 1.0 / self.eta };
+        let wm = (wi * etap + wo).try_normalize(1e-12)?;
+        let wm = if wm.z < 0.0 { -wm } else { wm };
+
+        // Microfacets facing away from either direction do not contribute
+        if wm.dot(wi) * cosThetaI < 0.0 || wm.dot(wo) * cosThetaO < 0.0 {
+            return None;
+        }
+        Some((wm, etap))
+    }
+}
+
+impl Bsdf for Dielectric {
+    fn evaluate(&self, wo: &Vector3<f32>, wi: &Vector3<f32>) -> Vector3<f32> {
+        if self.eta == 1.0 || self.distribution.isSmooth() {
+            return Vector3::zeros();
+        }
+        let (wm, etap) = match self.generalizedHalfVector(wo, wi) {
+            Some(half) => half,
+            None => return Vector3::zeros(),
+        };
+
+        let (cosThetaO, cosThetaI) = (CosTheta(wo), CosTheta(wi));
+        let fresnel = FresnelDielectric(wo.dot(&wm), self.eta);
+        let d = self.distribution.D(&wm);
+        let g = self.distribution.G(wo, wi);
+
+        if SameHemisphere(wo, wi) {
+            return Vector3::repeat(d * g * fresnel / (4.0 * cosThetaI * cosThetaO).abs());
+        }
+
+        let denominator = (wi.dot(&wm) + wo.dot(&wm) / etap).powi(2) * cosThetaI * cosThetaO;
+        let transmission = d * (1.0 - fresnel) * g * (wi.dot(&wm) * wo.dot(&wm) / denominator).abs();
+        Vector3::repeat(transmission / (etap * etap))
+    }
+
+    fn sample(&self, wo: &Vector3<f32>, u: &Vector2<f32>, uc: f32) -> Option<BsdfSample> {
+        if self.eta == 1.0 || self.distribution.isSmooth() {
+            let reflectance = FresnelDielectric(CosTheta(wo), self.eta);
+            if uc < reflectance {
+                let wi = Vector3::new(-wo.x, -wo.y, wo.z);
+                return Some(BsdfSample { direction: wi, weight: Vector3::repeat(1.0), pdf: reflectance, isSpecular: true });
+            }
+
+            let (wi, etap) = Refract(wo, &Vector3::z(), self.eta)?;
+            let weight = Vector3::repeat(1.0 / (etap * etap));
+            return Some(BsdfSample { direction: wi, weight, pdf: 1.0 - reflectance, isSpecular: true });
+        }
+
+        if wo.z == 0.0 {
+            return None;
+        }
+        let wm = self.distribution.sampleVisibleNormal(wo, u);
+        let reflectance = FresnelDielectric(wo.dot(&wm), self.eta);
+
+        let wi = if uc < reflectance {
+            let wi = Reflect(wo, &wm);
+            if !SameHemisphere(wo, &wi) {
+                return None;
+            }
+            wi
+        } else {
+            let (wi, _) = Refract(wo, &wm, self.eta)?;
+            if SameHemisphere(wo, &wi) || wi.z == 0.0 {
+                return None;
+            }
+            wi
+        };
+
+        let pdf = self.pdf(wo, &wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        let weight = self.evaluate(wo, &wi) * CosTheta(&wi).abs() / pdf;
+        Some(BsdfSample { direction: wi, weight, pdf, isSpecular: false })
+    }
+
+    fn pdf(&self, wo: &Vector3<f32>, wi: &Vector3<f32>) -> f32 {
+        if self.eta == 1.0 || self.distribution.isSmooth() {
+            return 0.0;
+        }
+        let (wm, etap) = match self.generalizedHalfVector(wo, wi) {
+            Some(half) => half,
+            None => return 0.0,
+        };
+
+        let reflectance = FresnelDielectric(wo.dot(&wm), self.eta);
+        let visiblePdf = self.distribution.visiblePdf(wo, &wm);
+        if SameHemisphere(wo, wi) {
+            visiblePdf / (4.0 * wo.dot(&wm).abs()) * reflectance
+        } else {
+            let jacobian = wi.dot(&wm).abs() / (wi.dot(&wm) + wo.dot(&wm) / etap).powi(2);
+            visiblePdf * jacobian * (1.0 - reflectance)
+        }
+    }
+
+    fn isSpecular(&self) -> bool {
+        self.eta == 1.0 || self.distribution.isSmooth()
+    }
+}
+
+// Diffuse base under a dielectric coating with GGX roughness. Light that is not reflected by the
+// coating reaches the base and scatters diffusely, including internal reflections at the coating
+// (after the rough plastic model in Mitsuba).
+pub struct Plastic {
+    pub diffuse: Vector3<f32>,
+    pub eta: f32,
+    pub distribution: GgxDistribution,
+}
+
+impl Plastic {
+    fn specularProbability(&self, wo: &Vector3<f32>) -> f32 {
+        let specular = FresnelDielectric(CosTheta(wo), self.eta);
+        let diffuse = (1.0 - specular) * self.diffuse.mean();
+        if specular + diffuse <= 0.0 { 1.0 } else { specular / (specular + diffuse) }
+    }
+
+    fn specularOnly(&self) -> Conductor {
+        // The coating reflects like a conductor with a real index of refraction
+        Conductor { eta: Vector3::repeat(self.eta), k: Vector3::zeros(), distribution: self.distribution }
+    }
+
+    fn diffuseTerm(&self, wo: &Vector3<f32>, wi: &Vector3<f32>) -> Vector3<f32> {
+        let internalReflectance = InternalDiffuseReflectance(self.eta);
+        let transmission = (1.0 - FresnelDielectric(CosTheta(wo), self.eta)) * (1.0 - FresnelDielectric(CosTheta(wi), self.eta));
+        let scattering = self.diffuse.map(|albedo| albedo / (1.0 - albedo * internalReflectance));
+        scattering * (transmission / (PI * self.eta * self.eta))
+    }
+}
+
+impl Bsdf for Plastic {
+    fn evaluate(&self, wo: &Vector3<f32>, wi: &Vector3<f32>) -> Vector3<f32> {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return Vector3::zeros();
+        }
+        self.specularOnly().evaluate(wo, wi) + self.diffuseTerm(wo, wi)
+    }
+
+    fn sample(&self, wo: &Vector3<f32>, u: &Vector2<f32>, uc: f32) -> Option<BsdfSample> {
+        if wo.z <= 0.0 {
+            return None;
+        }
+
+        let specularProbability = self.specularProbability(wo);
+        let coating = self.specularOnly();
+
+        if uc < specularProbability {
+            let sample = coating.sample(wo, u, 0.0)?;
+            if sample.isSpecular {
+                // A smooth coating, the diffuse lobe cannot be reached by this direction
+                return Some(BsdfSample {
+                    weight: sample.weight / specularProbability,
+                    pdf: sample.pdf * specularProbability,
+                    ..sample
+                });
+            }
+            let pdf = self.pdf(wo, &sample.direction);
+            let weight = self.evaluate(wo, &sample.direction) * CosTheta(&sample.direction) / pdf;
+            return Some(BsdfSample { direction: sample.direction, weight, pdf, isSpecular: false });
+        }
+
+        let wi = SampleCosineHemisphere(u);
+        let pdf = self.pdf(wo, &wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        let weight = self.evaluate(wo, &wi) * CosTheta(&wi) / pdf;
+        Some(BsdfSample { direction: wi, weight, pdf, isSpecular: false })
+    }
+
+    fn pdf(&self, wo: &Vector3<f32>, wi: &Vector3<f32>) -> f32 {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+        let specularProbability = self.specularProbability(wo);
+        specularProbability * self.specularOnly().pdf(wo, wi) + (1.0 - specularProbability) * CosTheta(wi) / PI
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::Rng;
+
+    const SAMPLE_COUNT: u32 = 1 << 14;
+
+    fn Direction(thetaDegrees: f32, phiDegrees: f32) -> Vector3<f32> {
+        let (theta, phi) = (thetaDegrees.to_radians(), phiDegrees.to_radians());
+        Vector3::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos())
+    }
+
+    fn Viewing() -> Vec<Vector3<f32>> {
+        [0.0, 30.0, 60.0, 85.0].iter().map(|&theta| Direction(theta, 40.0)).collect()
+    }
+
+    // Mean sample weight, which estimates the directional albedo. Transmitted weights are scaled
+    // by the given factor.
+    fn Albedo(bsdf: &dyn Bsdf, wo: &Vector3<f32>, transmissionScale: f32) -> f32 {
+        let mut rng = Rng::new(7, 11);
+        let mut total = 0.0;
+        for _ in 0..SAMPLE_COUNT {
+            if let Some(sample) = bsdf.sample(wo, &rng.next2D(), rng.nextF32()) {
+                let scale = if SameHemisphere(wo, &sample.direction) { 1.0 } else { transmissionScale };
+                total += sample.weight.mean() * scale;
+            }
+        }
+        total / SAMPLE_COUNT as f32
+    }
+
+    fn Rough() -> Vec<GgxDistribution> {
+        [0.2, 0.5, 1.0].iter().map(|&roughness| GgxDistribution::fromRoughness(roughness)).collect()
+    }
+
+    #[test]
+    fn LambertianIntegratesToAlbedo() {
+        let lambertian = Lambertian { albedo: Vector3::new(1.0, 1.0, 1.0) };
+
+        // Integrates evaluate over the sphere on a grid, independent of the sampling routine
+        let resolution = 512;
+        for wo in Viewing() {
+            let mut total = 0.0;
+            for i in 0..resolution {
+                for j in 0..resolution {
+                    let z = 1.0 - 2.0 * (i as f32 + 0.5) / resolution as f32;
+                    let phi = 2.0 * PI * (j as f32 + 0.5) / resolution as f32;
+                    let radius = (1.0 - z * z).sqrt();
+                    let wi = Vector3::new(radius * phi.cos(), radius * phi.sin(), z);
+                    total += lambertian.evaluate(&wo, &wi).x * z.abs();
+                }
+            }
+            let integral = total * 4.0 * PI / (resolution * resolution) as f32;
+            assert!((integral - 1.0).abs() < 1e-3, "{}", integral);
+            assert!((Albedo(&lambertian, &wo, 0.0) - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn ConductorDoesNotCreateEnergy() {
+        for roughness in [0.2, 0.5, 1.0] {
+            let conductor = Conductor::fromReflectance(&Vector3::new(1.0, 1.0, 1.0), roughness);
+            for wo in Viewing() {
+                let albedo = Albedo(&conductor, &wo, 0.0);
+                assert!(albedo <= 1.0, "roughness {} albedo {}", roughness, albedo);
+            }
+        }
+    }
+
+    #[test]
+    fn RoughDielectricConservesEnergy() {
+        // Radiance compression aside, light is either reflected or transmitted. Single scattering
+        // microfacet models lose the light bouncing between microfacets, which is negligible only
+        // for smooth surfaces away from grazing angles.
+        for distribution in Rough() {
+            for eta in [1.33, 1.5] {
+                let dielectric = Dielectric { eta, distribution };
+                for wo in Viewing() {
+                    for wo in [wo, -wo] {
+                        let etap = if wo.z > 0.0 { eta } else { 1.0 / eta };
+                        let albedo = Albedo(&dielectric, &wo, etap * etap);
+                        assert!(albedo <= 1.005, "alpha {} eta {} albedo {}", distribution.alpha, eta, albedo);
+                        if distribution.alpha < 0.1 && wo.z.abs() >= 0.5 {
+                            assert!(albedo > 0.99, "alpha {} eta {} albedo {}", distribution.alpha, eta, albedo);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn PlasticDoesNotCreateEnergy() {
+        for distribution in Rough() {
+            let plastic = Plastic { diffuse: Vector3::new(1.0, 1.0, 1.0), eta: 1.5, distribution };
+            for wo in Viewing() {
+                let albedo = Albedo(&plastic, &wo, 0.0);
+                assert!(albedo <= 1.01, "alpha {} albedo {}", distribution.alpha, albedo);
+            }
+        }
+    }
+
+    fn ReflectiveLobes() -> Vec<Box<dyn Bsdf>> {
+        let distribution = GgxDistribution::fromRoughness(0.5);
+        vec![
+            Box::new(Lambertian { albedo: Vector3::new(0.2, 0.5, 0.8) }),
+            Box::new(Conductor::fromReflectance(&Vector3::new(0.9, 0.6, 0.3), 0.5)),
+            Box::new(Dielectric { eta: 1.5, distribution }),
+            Box::new(Plastic { diffuse: Vector3::new(0.2, 0.5, 0.8), eta: 1.5, distribution }),
+        ]
+    }
+
+    #[test]
+    fn ReflectionIsReciprocal() {
+        let mut rng = Rng::new(3, 5);
+        for bsdf in ReflectiveLobes() {
+            for _ in 0..1000 {
+                let wo = SampleCosineHemisphere(&rng.next2D());
+                let wi = SampleCosineHemisphere(&rng.next2D());
+                let (forward, backward) = (bsdf.evaluate(&wo, &wi), bsdf.evaluate(&wi, &wo));
+                assert!((forward - backward).norm() <= 1e-4 * forward.norm().max(1.0), "{:?} {:?}", forward, backward);
+            }
+        }
+    }
+
+    #[test]
+    fn SamplesMatchPdfAndEvaluate() {
+        let mut rng = Rng::new(5, 9);
+        for bsdf in ReflectiveLobes() {
+            for wo in Viewing() {
+                for _ in 0..1000 {
+                    let sample = match bsdf.sample(&wo, &rng.next2D(), rng.nextF32()) {
+                        Some(sample) => sample,
+                        None => continue,
+                    };
+                    let pdf = bsdf.pdf(&wo, &sample.direction);
+                    assert!((sample.pdf - pdf).abs() <= 1e-3 * pdf, "{} {}", sample.pdf, pdf);
+
+                    let weight = bsdf.evaluate(&wo, &sample.direction) * CosTheta(&sample.direction).abs() / pdf;
+                    assert!((sample.weight - weight).norm() <= 1e-3 * weight.norm().max(1.0), "{:?} {:?}", sample.weight, weight);
+                }
+            }
+        }
+    }
+}
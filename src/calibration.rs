@@ -0,0 +1,372 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde_yaml::{Mapping, Value};
+
+use crate::camera::{Camera, Intrinsics};
+use crate::distortion::Distortion;
+
+// Calibration file layouts, both YAML with the same keys but different matrix encodings:
+//
+//     OpenCV FileStorage                          ROS camera_calibration_parsers
+//     %YAML:1.0                                   image_width: 640
+//     ---                                         image_height: 480
+//     image_width: 640                            camera_name: camera
+//     image_height: 480                           camera_matrix:
+//     camera_matrix: !!opencv-matrix                rows: 3
+//        rows: 3                                    cols: 3
+//        cols: 3                                    data: [fx, skew, cx, 0, fy, cy, 0, 0, 1]
+//        dt: d                                    distortion_model: plumb_bob
+//        data: [fx, skew, cx, 0, fy, cy, 0, 0, 1] distortion_coefficients:
+//     distortion_coefficients: !!opencv-matrix      rows: 1
+//        rows: 1                                    cols: 5
+//        cols: 5                                    data: [k1, k2, p1, p2, k3]
+//        dt: d                                    rectification_matrix: ...
+//        data: [k1, k2, p1, p2, k3]               projection_matrix: ...
+//
+// Reading also accepts a dumped sensor_msgs/CameraInfo message with width, height, k, d and
+// distortion_model. Distortion models use the ROS names plumb_bob, rational_polynomial and
+// equidistant, OpenCV files without one are Brown-Conrady.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CalibrationFormat {
+    OpenCv,
+    Ros,
+}
+
+impl CalibrationFormat {
+    pub const ALL: [CalibrationFormat; 2] = [CalibrationFormat::OpenCv, CalibrationFormat::Ros];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CalibrationFormat::OpenCv => "OpenCV YAML",
+            CalibrationFormat::Ros => "ROS CameraInfo",
+        }
+    }
+
+    // Command line spelling
+    pub fn key(&self) -> &'static str {
+        match self {
+            CalibrationFormat::OpenCv => "opencv",
+            CalibrationFormat::Ros => "ros",
+        }
+    }
+
+    pub fn fromKey(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|format| format.key() == key)
+    }
+}
+
+#[derive(Debug)]
+pub enum CalibrationError {
+    Io { path: PathBuf, error: io::Error },
+    Parse { path: PathBuf, error: serde_yaml::Error },
+    Invalid { path: PathBuf, message: String },
+}
+
+impl fmt::Display for CalibrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CalibrationError::Io { path, error } => write!(f, "cannot access calibration file '{}': {}", path.display(), error),
+            CalibrationError::Parse { path, error } => write!(f, "invalid calibration file '{}': {}", path.display(), error),
+            CalibrationError::Invalid { path, message } => write!(f, "invalid calibration file '{}': {}", path.display(), message),
+        }
+    }
+}
+
+impl std::error::Error for CalibrationError {}
+
+// Everything a calibration file stores about the camera model
+#[derive(Clone, Debug, PartialEq)]
+pub struct Calibration {
+    pub cameraName: String,
+    pub imageWidth: u32,
+    pub imageHeight: u32,
+    pub intrinsics: Intrinsics,
+    pub distortion: Distortion,
+}
+
+impl Calibration {
+    pub fn fromCamera(camera: &Camera) -> Self {
+        Self {
+            cameraName: String::from("camera"),
+            imageWidth: camera.imageWidth as u32,
+            imageHeight: camera.imageHeight as u32,
+            intrinsics: camera.intrinsics(),
+            distortion: camera.distortion,
+        }
+    }
+
+    // LoadCalibration only returns valid calibrations, ones built by hand may not be
+    pub fn isValid(&self) -> bool {
+        self.imageWidth > 0 && self.imageHeight > 0 && self.intrinsics.isValid()
+    }
+
+    // Sets the resolution, intrinsics and distortion of the camera. Invalid calibrations return
+    // false and leave the camera unchanged.
+    pub fn apply(&self, camera: &mut Camera) -> bool {
+        if !self.isValid() {
+            return false;
+        }
+        camera.resize(self.imageWidth as f32, self.imageHeight as f32);
+        camera.setIntrinsics(&self.intrinsics);
+        camera.setDistortion(self.distortion);
+        true
+    }
+}
+
+pub fn LoadCalibration(path: &Path) -> Result<Calibration, CalibrationError> {
+    let text = fs::read_to_string(path).map_err(|error| CalibrationError::Io { path: path.to_path_buf(), error })?;
+    ParseCalibration(&text).map_err(|error| match error {
+        ParseError::Yaml(error) => CalibrationError::Parse { path: path.to_path_buf(), error },
+        ParseError::Invalid(message) => CalibrationError::Invalid { path: path.to_path_buf(), message },
+    })
+}
+
+pub fn SaveCalibration(path: &Path, calibration: &Calibration, format: CalibrationFormat) -> Result<(), CalibrationError> {
+    fs::write(path, FormatCalibration(calibration, format)).map_err(|error| CalibrationError::Io { path: path.to_path_buf(), error })
+}
+
+enum ParseError {
+    Yaml(serde_yaml::Error),
+    Invalid(String),
+}
+
+// Reads either format, telling them apart is not needed since they share their keys
+fn ParseCalibration(text: &str) -> Result<Calibration, ParseError> {
+    // OpenCV writes a YAML 1.0 directive that YAML parsers no longer accept
+    let text = match text.trim_start().strip_prefix("%YAML") {
+        Some(rest) => rest.split_once('\n').map_or("", |(_, rest)| rest),
+        None => text,
+    };
+    let document = Untag(serde_yaml::from_str::<Value>(text).map_err(ParseError::Yaml)?);
+    let document = document.as_mapping().ok_or_else(|| ParseError::Invalid(String::from("expected a mapping of calibration entries")))?;
+
+    let size = |keys: &[&str]| -> Result<u32, ParseError> {
+        match Lookup(document, keys).and_then(Value::as_u64) {
+            Some(size) if size > 0 && size <= u32::MAX as u64 => Ok(size as u32),
+            _ => Err(ParseError::Invalid(format!("{} must be a positive integer", keys[0]))),
+        }
+    };
+    let imageWidth = size(&["image_width", "width"])?;
+    let imageHeight = size(&["image_height", "height"])?;
+
+    let cameraMatrix = Lookup(document, &["camera_matrix", "k", "K"])
+        .ok_or_else(|| ParseError::Invalid(String::from("camera_matrix is missing")))?;
+    let cameraMatrix = MatrixData(cameraMatrix)
+        .filter(|data| data.len() == 9)
+        .ok_or_else(|| ParseError::Invalid(String::from("camera_matrix must have 9 numbers")))?;
+    let intrinsics = Intrinsics::fromMatrix(&nalgebra::Matrix3::from_row_slice(&cameraMatrix))
+        .ok_or_else(|| ParseError::Invalid(String::from("camera_matrix must be upper triangular with positive focal lengths")))?;
+
+    let coefficients = match Lookup(document, &["distortion_coefficients", "d", "D"]) {
+        Some(value) => MatrixData(value)
+            .filter(|data| data.iter().all(|value| value.is_finite()))
+            .ok_or_else(|| ParseError::Invalid(String::from("distortion_coefficients must be finite numbers")))?,
+        None => Vec::new(),
+    };
+    let model = Lookup(document, &["distortion_model"]).and_then(Value::as_str).unwrap_or("plumb_bob");
+    let distortion = ParseDistortion(model, &coefficients)?;
+
+    let cameraName = Lookup(document, &["camera_name"]).and_then(Value::as_str).unwrap_or("camera").to_string();
+    Ok(Calibration { cameraName, imageWidth, imageHeight, intrinsics, distortion })
+}
+
+fn ParseDistortion(model: &str, coefficients: &[f32]) -> Result<Distortion, ParseError> {
+    let key = match model {
+        "plumb_bob" | "rational_polynomial" | "radtan" | "brown-conrady" => "brown-conrady",
+        "equidistant" | "fisheye" | "kannala_brandt" | "kannala-brandt" => "kannala-brandt",
+        _ => return Err(ParseError::Invalid(format!("unsupported distortion_model '{}'", model))),
+    };
+
+    // Calibration tools write five zeros for an ideal lens, a fisheye without coefficients is
+    // still a fisheye
+    if key == "brown-conrady" && coefficients.iter().all(|&c| c == 0.0) {
+        return Ok(Distortion::None);
+    }
+    Distortion::fromCoefficients(key, coefficients).ok_or_else(|| {
+        ParseError::Invalid(format!("{} coefficients do not fit distortion_model '{}'", coefficients.len(), model))
+    })
+}
+
+// Strips YAML tags such as !!opencv-matrix, which only tell OpenCV how to decode the mapping
+fn Untag(value: Value) -> Value {
+    match value {
+        Value::Tagged(tagged) => Untag(tagged.value),
+        Value::Mapping(mapping) => Value::Mapping(mapping.into_iter().map(|(key, value)| (key, Untag(value))).collect()),
+        Value::Sequence(sequence) => Value::Sequence(sequence.into_iter().map(Untag).collect()),
+        value => value,
+    }
+}
+
+fn Lookup<'a>(mapping: &'a Mapping, keys: &[&str]) -> Option<&'a Value> {
+    keys.iter().find_map(|&key| mapping.get(key))
+}
+
+// Numbers of a matrix stored either as rows/cols/data or as a plain list
+fn MatrixData(value: &Value) -> Option<Vec<f32>> {
+    let data = match value {
+        Value::Mapping(mapping) => mapping.get("data")?,
+        value => value,
+    };
+    data.as_sequence()?.iter().map(|number| number.as_f64().map(|number| number as f32)).collect()
+}
+
+// ROS model name with its coefficients, plumb_bob with zeros for an ideal lens
+fn DistortionModel(distortion: &Distortion) -> (&'static str, Vec<f32>) {
+    let mut coefficients = distortion.coefficients();
+    match distortion {
+        Distortion::None => ("plumb_bob", vec![0.0; 5]),
+        Distortion::BrownConrady { .. } if coefficients.len() == 8 => ("rational_polynomial", coefficients),
+        Distortion::BrownConrady { .. } => {
+            coefficients.resize(5, 0.0);
+            ("plumb_bob", coefficients)
+        }
+        Distortion::KannalaBrandt { .. } => ("equidistant", coefficients),
+    }
+}
+
+fn FormatList(values: &[f32]) -> String {
+    values.iter().map(|value| format!("{:?}", value)).collect::<Vec<_>>().join(", ")
+}
+
+pub fn FormatCalibration(calibration: &Calibration, format: CalibrationFormat) -> String {
+    let k = &calibration.intrinsics;
+    let cameraMatrix = [k.fx, k.skew, k.cx, 0.0, k.fy, k.cy, 0.0, 0.0, 1.0];
+    let (model, coefficients) = DistortionModel(&calibration.distortion);
+
+    match format {
+        CalibrationFormat::OpenCv => {
+            let matrix = |name: &str, rows: usize, data: &[f32]| {
+                format!(
+                    "{}: !!opencv-matrix\n   rows: {}\n   cols: {}\n   dt: d\n   data: [ {} ]\n",
+                    name, rows, data.len() / rows, FormatList(data)
+                )
+            };
+            format!(
+                "%YAML:1.0\n---\nimage_width: {}\nimage_height: {}\n{}distortion_model: {}\n{}",
+                calibration.imageWidth,
+                calibration.imageHeight,
+                matrix("camera_matrix", 3, &cameraMatrix),
+                model,
+                matrix("distortion_coefficients", 1, &coefficients),
+            )
+        }
+        CalibrationFormat::Ros => {
+            let matrix = |name: &str, rows: usize, data: &[f32]| {
+                format!("{}:\n  rows: {}\n  cols: {}\n  data: [{}]\n", name, rows, data.len() / rows, FormatList(data))
+            };
+            // The image is not rectified, the projection matrix is the camera matrix of a monocular camera
+            let projectionMatrix = [k.fx, k.skew, k.cx, 0.0, 0.0, k.fy, k.cy, 0.0, 0.0, 0.0, 1.0, 0.0];
+            format!(
+                "image_width: {}\nimage_height: {}\ncamera_name: {}\n{}distortion_model: {}\n{}{}{}",
+                calibration.imageWidth,
+                calibration.imageHeight,
+                calibration.cameraName,
+                matrix("camera_matrix", 3, &cameraMatrix),
+                model,
+                matrix("distortion_coefficients", 1, &coefficients),
+                matrix("rectification_matrix", 3, &[1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0]),
+                matrix("projection_matrix", 3, &projectionMatrix),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn Calibrations() -> Vec<Calibration> {
+        let intrinsics = Intrinsics { fx: 612.5, fy: 611.75, cx: 318.25, cy: 241.5, skew: 0.0 };
+        [
+            Distortion::None,
+            Distortion::fromCoefficients("brown-conrady", &[-0.28, 0.07, 0.0008, -0.0005, 0.01]).unwrap(),
+            Distortion::fromCoefficients("brown-conrady", &[1.2, 0.4, 0.0003, -0.0002, 0.01, 1.5, 0.6, 0.05]).unwrap(),
+            Distortion::fromCoefficients("kannala-brandt", &[0.02, -0.005, 0.001, -0.0002]).unwrap(),
+        ]
+        .into_iter()
+        .map(|distortion| Calibration { cameraName: String::from("camera"), imageWidth: 640, imageHeight: 480, intrinsics, distortion })
+        .collect()
+    }
+
+    #[test]
+    fn FormatsRoundTrip() {
+        for format in CalibrationFormat::ALL {
+            for calibration in Calibrations() {
+                let text = FormatCalibration(&calibration, format);
+                assert_eq!(ParseCalibration(&text).ok(), Some(calibration), "{}:\n{}", format.name(), text);
+            }
+        }
+
+        let named = Calibration { cameraName: String::from("left"), ..Calibrations().remove(0) };
+        let text = FormatCalibration(&named, CalibrationFormat::Ros);
+        assert_eq!(ParseCalibration(&text).ok(), Some(named));
+    }
+
+    #[test]
+    fn FormatKeysRoundTrip() {
+        for format in CalibrationFormat::ALL {
+            assert_eq!(CalibrationFormat::fromKey(format.key()), Some(format));
+            assert!(!format.name().is_empty());
+        }
+        assert_eq!(CalibrationFormat::fromKey("yaml"), None);
+    }
+
+    #[test]
+    fn ParsesCameraInfoMessages() {
+        let text = "width: 640\nheight: 480\ndistortion_model: equidistant\nd: [0.02, -0.005, 0.001, -0.0002]\nk: [600.0, 0.0, 320.0, 0.0, 600.0, 240.0, 0.0, 0.0, 1.0]\n";
+        let calibration = ParseCalibration(text).ok().unwrap();
+        assert_eq!((calibration.imageWidth, calibration.imageHeight), (640, 480));
+        assert_eq!(calibration.intrinsics, Intrinsics { fx: 600.0, fy: 600.0, cx: 320.0, cy: 240.0, skew: 0.0 });
+        assert_eq!(calibration.distortion, Distortion::KannalaBrandt { k: [0.02, -0.005, 0.001, -0.0002] });
+
+        let singular = text.replace("600.0, 240.0", "0.0, 240.0");
+        assert!(ParseCalibration(&singular).is_err());
+    }
+
+    #[test]
+    fn RejectsInvalidNumbers() {
+        let text = "width: 640\nheight: 480\nd: [0.1, 0.0, 0.0, 0.0, 0.0]\nk: [600.0, 0.0, 320.0, 0.0, 600.0, 240.0, 0.0, 0.0, 1.0]\n";
+        assert!(ParseCalibration(text).is_ok());
+        for (from, to) in [
+            ("width: 640", "width: 0"),
+            ("width: 640", "width: -640"),
+            ("[600.0, 0.0, 320.0", "[.nan, 0.0, 320.0"),
+            ("[600.0, 0.0, 320.0", "[-600.0, 0.0, 320.0"),
+            ("320.0, 0.0, 600.0", "320.0, 0.0, .inf"),
+            ("0.0, 0.0, 1.0]", "0.0, 0.5, 1.0]"),
+            ("d: [0.1", "d: [.nan"),
+        ] {
+            let invalid = text.replace(from, to);
+            assert!(ParseCalibration(&invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn ApplyMatchesFromCamera() {
+        let mut camera = Camera::new(nalgebra::Matrix4::identity(), 60.0, 320.0, 240.0);
+        for calibration in Calibrations() {
+            assert!(calibration.apply(&mut camera));
+            assert!(camera.hasExplicitIntrinsics());
+            assert_eq!(Calibration::fromCamera(&camera), calibration);
+        }
+    }
+
+    #[test]
+    fn InvalidCalibrationsLeaveTheCameraAlone() {
+        let mut camera = Camera::new(nalgebra::Matrix4::identity(), 60.0, 320.0, 240.0);
+        let valid = Calibrations().remove(1);
+        let invalid = [
+            Calibration { imageWidth: 0, ..valid.clone() },
+            Calibration { intrinsics: Intrinsics { fy: 0.0, ..valid.intrinsics }, ..valid.clone() },
+            Calibration { intrinsics: Intrinsics { cx: f32::NAN, ..valid.intrinsics }, ..valid.clone() },
+        ];
+        for calibration in invalid {
+            assert!(!calibration.apply(&mut camera));
+            assert!(!camera.hasExplicitIntrinsics());
+            assert_eq!((camera.imageWidth, camera.imageHeight, camera.distortion), (320.0, 240.0, Distortion::None));
+        }
+    }
+}
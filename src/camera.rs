@@ -1,7 +1,17 @@
-use nalgebra::{Matrix3, Matrix4, Point3, Rotation3, SMatrix, UnitVector3, Vector3, Vector4};
+use std::f32::consts::PI;
+
+use nalgebra::{Matrix3, Matrix4, Point3, Rotation3, UnitVector3, Vector2, Vector3, Vector4};
 
 use itertools::Itertools;
 
+use crate::distortion::Distortion;
+use crate::intersection::Ray;
+use crate::light::{EquirectDirection, EquirectUv};
+use crate::projection::{CubemapDirection, CubemapPosition, FisheyeDirection, FisheyeOffset, Projection};
+use crate::sampling::{SampleConcentricDisk, SampleRegularPolygon};
+use crate::vec_ops::Transform;
+use crate::world::World;
+
 fn ComputeCameraMatrix(verticalFOVDegrees: f32, imageWidth: f32, imageHeight: f32) -> Matrix3<f32> {
     // Convert vertical FOV from degrees to radians
     let verticalFOVRadians = verticalFOVDegrees.to_radians();
@@ -28,11 +38,77 @@ fn ComputeCameraMatrix(verticalFOVDegrees: f32, imageWidth: f32, imageHeight: f3
     cameraMatrix
 }
 
+// Pinhole intrinsics in pixels with OpenCV's convention of pixel centers at integer coordinates,
+// for cameras calibrated against real sensors. Non-square pixels have fx != fy.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Intrinsics {
+    pub fx: f32,
+    pub fy: f32,
+    pub cx: f32,
+    pub cy: f32,
+    pub skew: f32,
+}
+
+impl Intrinsics {
+    pub fn matrix(&self) -> Matrix3<f32> {
+        Matrix3::new(
+            self.fx, self.skew, self.cx,
+            0.0, self.fy, self.cy,
+            0.0, 0.0, 1.0,
+        )
+    }
+
+    // None unless the matrix is upper triangular with positive focal lengths and a unit corner
+    pub fn fromMatrix(matrix: &Matrix3<f32>) -> Option<Self> {
+        let intrinsics = Self { fx: matrix[(0, 0)], fy: matrix[(1, 1)], cx: matrix[(0, 2)], cy: matrix[(1, 2)], skew: matrix[(0, 1)] };
+        let lower = [matrix[(1, 0)], matrix[(2, 0)], matrix[(2, 1)]];
+        (intrinsics.isValid() && lower.iter().all(|&value| value == 0.0) && matrix[(2, 2)] == 1.0).then_some(intrinsics)
+    }
+
+    pub fn isValid(&self) -> bool {
+        self.fx > 0.0 && self.fy > 0.0 && [self.fx, self.fy, self.cx, self.cy, self.skew].iter().all(|value| value.is_finite())
+    }
+
+    // Intrinsics of the same lens on an image resized by the given factors
+    pub fn scaled(&self, scaleX: f32, scaleY: f32) -> Self {
+        Self {
+            fx: self.fx * scaleX,
+            fy: self.fy * scaleY,
+            cx: (self.cx + 0.5) * scaleX - 0.5,
+            cy: (self.cy + 0.5) * scaleY - 0.5,
+            skew: self.skew * scaleX,
+        }
+    }
+}
+
+// Where a world space point shows up in the final image
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PixelProjection {
+    pub pixel: Vector2<f32>,    // continuous final image position, pixel (x, y) spans [x, x + 1) x [y, y + 1)
+    pub depth: f32,             // distance along the viewing direction, like the depth AOV
+    pub visible: bool,          // inside the image and the field of view of the projection
+}
+
 fn GenerateHomogenousPixelCoordinates(imageWidth: u32, imageHeight: u32) -> impl Iterator<Item=Vector3<f32>> {
     (0..imageHeight).cartesian_product(0..imageWidth)
         .map(|(y, x)| Vector3::new(x as f32, y as f32, 1.0))
 }
 
+// Outline of the lens opening, which shapes the out of focus highlights
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ApertureShape {
+    Circular,
+    Polygonal { blades: u32 },
+}
+
+// Point of the R2 sequence (Roberts 2018), gives every pixel of getRays its own lens position
+fn R2Sample(index: u32) -> Vector2<f32> {
+    const PLASTIC: f64 = 1.324717957244746;
+    let (a1, a2) = (1.0 / PLASTIC, 1.0 / (PLASTIC * PLASTIC));
+    Vector2::new((0.5 + a1 * index as f64).fract() as f32, (0.5 + a2 * index as f64).fract() as f32)
+}
+
+#[derive(Clone)]
 pub struct Camera {
     transform: Matrix4<f32>,
     cameraMatrix: Matrix3<f32>,
@@ -40,6 +116,12 @@ pub struct Camera {
     pub verticalFov: f32,
     pub imageWidth: f32,
     pub imageHeight: f32,
+    pub apertureRadius: f32,        // zero for a pinhole camera
+    pub apertureShape: ApertureShape,
+    pub focusDistance: f32,         // distance of the sharp plane along the viewing direction
+    pub projection: Projection,
+    pub distortion: Distortion,     // lens distortion of the perspective projection
+    explicitIntrinsics: bool,       // cameraMatrix was given rather than derived from verticalFov
 }
 
 impl Camera {
@@ -52,17 +134,127 @@ impl Camera {
             verticalFov,
             imageWidth,
             imageHeight,
+            apertureRadius: 0.0,
+            apertureShape: ApertureShape::Circular,
+            focusDistance: 5.0,
+            projection: Projection::Perspective,
+            distortion: Distortion::None,
+            explicitIntrinsics: false,
         }
     }
 
-    pub fn getRays(&mut self) -> Vec<(f32, f32, f32, f32, f32, f32)> {
-        // Compute the rotation matrix for all pixels
+    // Camera with a calibrated intrinsic matrix instead of a field of view, None for invalid intrinsics
+    pub fn fromIntrinsics(transform: Matrix4<f32>, intrinsics: &Intrinsics, imageWidth: f32, imageHeight: f32) -> Option<Self> {
+        let mut camera = Self::new(transform, 45.0, imageWidth, imageHeight);
+        camera.setIntrinsics(intrinsics).then_some(camera)
+    }
+
+    // Angle subtended by one pixel at the image center, the initial spread of camera ray cones
+    pub fn pixelSpreadAngle(&self) -> f32 {
+        match self.projection {
+            Projection::Perspective => (2.0 * (self.verticalFov.to_radians() / 2.0).tan() / self.imageHeight).atan(),
+            Projection::Orthographic { .. } => 0.0,
+            Projection::FisheyeEquidistant { fov } | Projection::FisheyeEquisolid { fov } => {
+                fov.to_radians() / self.imageWidth.min(self.imageHeight)
+            }
+            Projection::Equirectangular => 2.0 * PI / self.imageWidth,
+            Projection::Cubemap => PI / self.imageHeight,
+        }
+    }
+
+    // World space viewing direction, the +z axis of the camera
+    pub fn forward(&self) -> Vector3<f32> {
+        let axis: Vector3<f32> = self.transform.fixed_view::<3, 1>(0, 2).into();
+        axis.normalize()
+    }
+
+    // Offset on the lens in camera space for a sample in [0, 1)^2
+    fn lensPoint(&self, lensSample: &Vector2<f32>) -> Vector2<f32> {
+        let unit = match self.apertureShape {
+            ApertureShape::Circular => SampleConcentricDisk(lensSample),
+            ApertureShape::Polygonal { blades } => SampleRegularPolygon(lensSample, blades),
+        };
+        unit * self.apertureRadius
+    }
+
+    // Camera space ray of the projection through a continuous camera pixel position, None where
+    // the projection does not cover the image such as outside a fisheye circle
+    fn projectedRay(&self, pixelX: f32, pixelY: f32) -> Option<(Vector3<f32>, Vector3<f32>)> {
+        let (width, height) = ((self.imageWidth as u32) as f32, (self.imageHeight as u32) as f32);
+        let offset = Vector2::new(pixelX - self.cameraMatrix[(0, 2)], pixelY - self.cameraMatrix[(1, 2)]);
+
+        let direction = match self.projection {
+            Projection::Perspective => {
+                let distorted = self.cameraMatrixInverse * Vector3::new(pixelX, pixelY, 1.0);
+                self.distortion.undistort(&distorted.xy())?
+            }
+            Projection::Orthographic { height: viewHeight } => {
+                let origin = offset * (viewHeight / height);
+                return Some((Vector3::new(origin.x, origin.y, 0.0), Vector3::z()));
+            }
+            Projection::FisheyeEquidistant { fov } => FisheyeDirection(&offset, width.min(height) / 2.0, fov, false)?,
+            Projection::FisheyeEquisolid { fov } => FisheyeDirection(&offset, width.min(height) / 2.0, fov, true)?,
+            // Both layouts are defined in the final image, which is rotated by 180 degrees. The
+            // panorama of an unrotated camera loads back as an environment map.
+            Projection::Equirectangular => {
+                EquirectDirection(&Vector2::new((width - 0.5 - pixelX) / width, (height - 0.5 - pixelY) / height))
+            }
+            Projection::Cubemap => CubemapDirection((width - 0.5 - pixelX) / width, (height - 0.5 - pixelY) / height),
+        };
+        Some((Vector3::zeros(), direction))
+    }
+
+    // Camera space ray from a point on the lens through the point of the focus plane that the
+    // projected ray of the pixel reaches. Without a lens sample the ray leaves the lens center.
+    fn cameraSpaceRay(&self, pixelX: f32, pixelY: f32, lensSample: Option<&Vector2<f32>>) -> Option<(Vector3<f32>, Vector3<f32>)> {
+        let (origin, direction) = self.projectedRay(pixelX, pixelY)?;
+        let lensSample = match lensSample {
+            // Wide angle distortion can reach directions parallel to the focus plane
+            Some(lensSample) if self.apertureRadius > 0.0 && self.projection.hasLens() && direction.z > 1e-4 => lensSample,
+            _ => return Some((origin, direction)),
+        };
+
+        let lens = self.lensPoint(lensSample);
+        let focus = origin + direction * (self.focusDistance / direction.z);
+        let origin = origin + Vector3::new(lens.x, lens.y, 0.0);
+        Some((origin, (focus - origin).normalize()))
+    }
+
+    // The renderer shows the camera image rotated by 180 degrees, rays of the final image undo
+    // the rotation so that they match getTransformedRays at pixel centers
+    fn worldSpaceRay(&self, imageX: f32, imageY: f32, lensSample: Option<&Vector2<f32>>) -> Option<Ray> {
+        let pixelX = (self.imageWidth as u32) as f32 - 0.5 - imageX;
+        let pixelY = (self.imageHeight as u32) as f32 - 0.5 - imageY;
+        let (origin, direction) = self.cameraSpaceRay(pixelX, pixelY, lensSample)?;
 
+        let origin = self.transform.transform_point(&Point3::from(origin));
+        let direction = self.transform.transform_vector(&direction);
+        Some(Ray::new(origin.coords, direction, self.pixelSpreadAngle()))
+    }
+
+    // Ray through a continuous position of the final image, where pixel (x, y) spans
+    // [x, x + 1) x [y, y + 1), leaving the lens center so that it is always in focus. None where
+    // the projection leaves the image empty.
+    pub fn generateRay(&self, imageX: f32, imageY: f32) -> Option<Ray> {
+        self.worldSpaceRay(imageX, imageY, None)
+    }
+
+    // Like generateRay, leaving the lens at the point picked by a sample in [0, 1)^2
+    pub fn generateLensRay(&self, imageX: f32, imageY: f32, lensSample: &Vector2<f32>) -> Option<Ray> {
+        self.worldSpaceRay(imageX, imageY, Some(lensSample))
+    }
+
+    // Camera space rays of every pixel. With an aperture every pixel samples its own point of the
+    // lens, so a single ray per pixel already shows the defocus blur as noise. Pixels the
+    // projection does not cover get a zero direction.
+    pub fn getRays(&mut self) -> Vec<(f32, f32, f32, f32, f32, f32)> {
         GenerateHomogenousPixelCoordinates(self.imageWidth as u32, self.imageHeight as u32)
-            .map(|pixelCoords| {
-                let direction = (&self.cameraMatrixInverse * pixelCoords).normalize();
+            .enumerate()
+            .map(|(index, pixelCoords)| {
+                let (origin, direction) = self.cameraSpaceRay(pixelCoords.x, pixelCoords.y, Some(&R2Sample(index as u32)))
+                    .unwrap_or((Vector3::zeros(), Vector3::zeros()));
                 (
-                    0.0, 0.0, 0.0,
+                    origin[0], origin[1], origin[2],
                     direction[0], direction[1], direction[2]
                 )
             })
@@ -70,15 +262,98 @@ impl Camera {
     }
 
     pub fn getTransformedRays(&mut self) -> Vec<(f32, f32, f32, f32, f32, f32)> {
-        // Extract the first 3 elements of the last column of the transform matrix and store them in a variable
         self.getRays().iter().map(|ray| {
-            let (_x1, _y1, _z1, x2, y2, z2) = *ray;
+            let (x1, y1, z1, x2, y2, z2) = *ray;
+            let newOrigin = self.transform * Vector4::<f32>::new(x1, y1, z1, 1.0);
             let newDir = self.transform * Vector4::<f32>::new(x2, y2, z2, 0.0);
-            let translation: Vector3<f32> = self.transform.fixed_view::<3, 1>(0, 3).into();
-            (translation.x, translation.y, translation.z, newDir.x, newDir.y, newDir.z)
+            (newOrigin.x, newOrigin.y, newOrigin.z, newDir.x, newDir.y, newDir.z)
         }).collect()
     }
 
+    fn worldToCamera(&self) -> Matrix4<f32> {
+        self.transform.try_inverse().unwrap_or_else(Matrix4::identity)
+    }
+
+    // Final image position of a camera space point seen through the lens center, None where the
+    // projection has no image of it, such as behind a pinhole camera
+    fn projectCameraSpace(&self, point: &Vector3<f32>) -> Option<PixelProjection> {
+        let (width, height) = ((self.imageWidth as u32) as f32, (self.imageHeight as u32) as f32);
+        let center = Vector2::new(self.cameraMatrix[(0, 2)], self.cameraMatrix[(1, 2)]);
+        let circleRadius = width.min(height) / 2.0;
+        let direction = || point.try_normalize(0.0);
+
+        let (cameraPixel, inField) = match self.projection {
+            Projection::Perspective => {
+                let distorted = self.distortion.distort(point)?;
+                ((self.cameraMatrix * Vector3::new(distorted.x, distorted.y, 1.0)).xy(), true)
+            }
+            Projection::Orthographic { height: viewHeight } => (center + point.xy() * (height / viewHeight), point.z >= 0.0),
+            Projection::FisheyeEquidistant { fov } => {
+                let offset = FisheyeOffset(&direction()?, circleRadius, fov, false);
+                (center + offset, offset.norm() <= circleRadius)
+            }
+            Projection::FisheyeEquisolid { fov } => {
+                let offset = FisheyeOffset(&direction()?, circleRadius, fov, true);
+                (center + offset, offset.norm() <= circleRadius)
+            }
+            Projection::Equirectangular => {
+                let position = EquirectUv(&direction()?);
+                (Vector2::new(width - 0.5 - position.x * width, height - 0.5 - position.y * height), true)
+            }
+            Projection::Cubemap => {
+                let position = CubemapPosition(&direction()?);
+                (Vector2::new(width - 0.5 - position.x * width, height - 0.5 - position.y * height), true)
+            }
+        };
+
+        // Undo the rotation of the camera image like worldSpaceRay does
+        let pixel = Vector2::new(width - 0.5 - cameraPixel.x, height - 0.5 - cameraPixel.y);
+        let inImage = pixel.x >= 0.0 && pixel.y >= 0.0 && pixel.x < width && pixel.y < height;
+        Some(PixelProjection { pixel, depth: point.z, visible: inField && inImage })
+    }
+
+    // Final image position of a world space point seen through the lens center, the inverse of
+    // generateRay. Occlusion by the scene is not checked.
+    pub fn projectPoint(&self, point: &Vector3<f32>) -> Option<PixelProjection> {
+        let cameraPoint = self.worldToCamera().transform_point(&Point3::from(*point));
+        self.projectCameraSpace(&cameraPoint.coords)
+    }
+
+    // projectPoint for many points, moved to camera space in one batch
+    pub fn projectPoints(&self, points: &[Vector3<f32>]) -> Vec<Option<PixelProjection>> {
+        let homogeneous: Vec<Vector4<f32>> = points.iter().map(|point| point.push(1.0)).collect();
+        homogeneous.transform(&self.worldToCamera()).iter()
+            .map(|point| self.projectCameraSpace(&point.xyz()))
+            .collect()
+    }
+
+    pub fn setAperture(&mut self, radius: f32, shape: ApertureShape) {
+        self.apertureRadius = radius.max(0.0);
+        self.apertureShape = shape;
+    }
+
+    pub fn setFocusDistance(&mut self, distance: f32) {
+        self.focusDistance = distance.max(1e-4);
+    }
+
+    // Focuses on the surface seen through the image center and returns its distance, leaving the
+    // focus alone when the center ray hits nothing
+    pub fn autofocus(&mut self, world: &World) -> Option<f32> {
+        let ray = self.generateRay((self.imageWidth as u32) as f32 / 2.0, (self.imageHeight as u32) as f32 / 2.0)?;
+        let hit = world.intersect(&ray.origin, &ray.direction)?;
+        let distance = hit.distance * ray.direction.dot(&self.forward());
+        self.setFocusDistance(distance);
+        Some(self.focusDistance)
+    }
+
+    pub fn setProjection(&mut self, projection: Projection) {
+        self.projection = projection;
+    }
+
+    pub fn setDistortion(&mut self, distortion: Distortion) {
+        self.distortion = distortion;
+    }
+
     pub fn setTransform(&mut self, transform: Matrix4<f32>) {
         self.transform = transform;
     }
@@ -91,20 +366,221 @@ impl Camera {
         self.transform.fixed_view_mut::<3, 1>(0, 3).copy_from(translation);
     }
 
+    // Places the camera at eye looking at target. The camera looks down its +z axis; with the image
+    // flipped by the renderer, its +y axis ends up pointing up in the final image.
+    pub fn lookAt(&mut self, eye: &Vector3<f32>, target: &Vector3<f32>, up: &Vector3<f32>) {
+        let forward = (target - eye).normalize();
+        let right = up.cross(&forward).normalize();
+        let cameraUp = forward.cross(&right);
+
+        let mut transform = Matrix4::<f32>::identity();
+        transform.fixed_view_mut::<3, 1>(0, 0).copy_from(&right);
+        transform.fixed_view_mut::<3, 1>(0, 1).copy_from(&cameraUp);
+        transform.fixed_view_mut::<3, 1>(0, 2).copy_from(&forward);
+        transform.fixed_view_mut::<3, 1>(0, 3).copy_from(eye);
+        self.setTransform(transform);
+    }
+
+    // Calibrated intrinsics are scaled with the image so the view stays the same
     pub fn resize(&mut self, imageWidth: f32, imageHeight: f32) {
+        let scale = (imageWidth / self.imageWidth, imageHeight / self.imageHeight);
         self.imageWidth = imageWidth;
         self.imageHeight = imageHeight;
-        self.recomputeCameraMatrix();
+
+        // From or to an empty image there is nothing to scale by, calibrated intrinsics stay as
+        // they are until the next real size
+        let scalable = [scale.0, scale.1].iter().all(|&factor| factor.is_finite() && factor > 0.0);
+        let intrinsics = if scalable { self.intrinsics().scaled(scale.0, scale.1) } else { self.intrinsics() };
+        if !self.explicitIntrinsics || !self.setIntrinsics(&intrinsics) {
+            self.recomputeCameraMatrix();
+        }
     }
 
+    // Replaces calibrated intrinsics with a centered matrix of square pixels
     pub fn setFov(&mut self, verticalFov: f32) {
         self.verticalFov = verticalFov;
+        self.explicitIntrinsics = false;
         self.recomputeCameraMatrix();
     }
 
+    pub fn intrinsics(&self) -> Intrinsics {
+        let m = &self.cameraMatrix;
+        Intrinsics { fx: m[(0, 0)], fy: m[(1, 1)], cx: m[(0, 2)], cy: m[(1, 2)], skew: m[(0, 1)] }
+    }
+
+    pub fn hasExplicitIntrinsics(&self) -> bool {
+        self.explicitIntrinsics
+    }
+
+    // Uses a calibrated intrinsic matrix until the next setFov. verticalFov follows fy so that
+    // ray cone spreads stay about right. Invalid intrinsics are rejected with false, leaving the
+    // camera unchanged.
+    pub fn setIntrinsics(&mut self, intrinsics: &Intrinsics) -> bool {
+        let cameraMatrix = intrinsics.matrix();
+        let cameraMatrixInverse = match cameraMatrix.try_inverse() {
+            Some(inverse) if intrinsics.isValid() => inverse,
+            _ => return false,
+        };
+        self.cameraMatrix = cameraMatrix;
+        self.cameraMatrixInverse = cameraMatrixInverse;
+        self.verticalFov = (2.0 * ((self.imageHeight - 1.0) / (2.0 * intrinsics.fy)).atan()).to_degrees();
+        self.explicitIntrinsics = true;
+        true
+    }
+
+    pub fn setFocalLength(&mut self, fx: f32, fy: f32) -> bool {
+        self.setIntrinsics(&Intrinsics { fx, fy, ..self.intrinsics() })
+    }
+
+    pub fn setPrincipalPoint(&mut self, cx: f32, cy: f32) -> bool {
+        self.setIntrinsics(&Intrinsics { cx, cy, ..self.intrinsics() })
+    }
+
+    pub fn setSkew(&mut self, skew: f32) -> bool {
+        self.setIntrinsics(&Intrinsics { skew, ..self.intrinsics() })
+    }
+
     fn recomputeCameraMatrix(&mut self) {
         let _cameraMatrix = ComputeCameraMatrix(self.verticalFov, self.imageWidth, self.imageHeight);
         self.cameraMatrix = _cameraMatrix;
         self.cameraMatrixInverse = _cameraMatrix.try_inverse().unwrap();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTRINSICS: Intrinsics = Intrinsics { fx: 500.0, fy: 480.0, cx: 330.0, cy: 235.0, skew: 0.5 };
+
+    #[test]
+    fn FromIntrinsicsKeepsTheCalibratedMatrix() {
+        let camera = Camera::fromIntrinsics(Matrix4::identity(), &INTRINSICS, 640.0, 480.0).unwrap();
+        assert!(camera.hasExplicitIntrinsics());
+        assert_eq!(camera.intrinsics(), INTRINSICS);
+        assert!(!Camera::new(Matrix4::identity(), 60.0, 640.0, 480.0).hasExplicitIntrinsics());
+
+        let invalid = Intrinsics { fx: 0.0, ..INTRINSICS };
+        assert!(Camera::fromIntrinsics(Matrix4::identity(), &invalid, 640.0, 480.0).is_none());
+    }
+
+    #[test]
+    fn SettersRejectInvalidIntrinsics() {
+        let mut camera = Camera::new(Matrix4::identity(), 60.0, 640.0, 480.0);
+        assert!(camera.setFocalLength(INTRINSICS.fx, INTRINSICS.fy));
+        assert!(camera.setPrincipalPoint(INTRINSICS.cx, INTRINSICS.cy));
+        assert!(camera.setSkew(INTRINSICS.skew));
+        assert_eq!(camera.intrinsics(), INTRINSICS);
+        assert!(camera.hasExplicitIntrinsics());
+
+        assert!(!camera.setFocalLength(0.0, 0.0));
+        assert!(!camera.setFocalLength(-500.0, 480.0));
+        assert!(!camera.setPrincipalPoint(f32::NAN, 235.0));
+        assert!(!camera.setSkew(f32::INFINITY));
+        assert_eq!(camera.intrinsics(), INTRINSICS);
+
+        camera.setFov(60.0);
+        assert!(!camera.hasExplicitIntrinsics());
+    }
+
+    #[test]
+    fn ResizeScalesCalibratedIntrinsics() {
+        let mut camera = Camera::fromIntrinsics(Matrix4::identity(), &INTRINSICS, 640.0, 480.0).unwrap();
+        let point = Vector3::new(0.4, -0.3, 2.0);
+        let before = camera.projectPoint(&point).unwrap();
+
+        camera.resize(320.0, 240.0);
+        assert_eq!(camera.intrinsics(), INTRINSICS.scaled(0.5, 0.5));
+        assert!(camera.hasExplicitIntrinsics());
+
+        // The same lens on a smaller sensor sees the point at the same relative position
+        let after = camera.projectPoint(&point).unwrap();
+        assert!((after.pixel - before.pixel * 0.5).norm() < 1e-3, "{:?} {:?}", before.pixel, after.pixel);
+    }
+
+    #[test]
+    fn ResizeKeepsCalibratedIntrinsicsThroughEmptyImages() {
+        let mut camera = Camera::fromIntrinsics(Matrix4::identity(), &INTRINSICS, 640.0, 480.0).unwrap();
+        for (width, height) in [(0.0, 0.0), (640.0, 480.0), (640.0, 0.0), (640.0, 480.0)] {
+            camera.resize(width, height);
+            assert!(camera.hasExplicitIntrinsics(), "{}x{}", width, height);
+            assert_eq!(camera.intrinsics(), INTRINSICS, "{}x{}", width, height);
+        }
+    }
+
+    fn Distortions() -> Vec<Distortion> {
+        vec![
+            Distortion::None,
+            Distortion::fromCoefficients("brown-conrady", &[-0.2, 0.05, 0.001, -0.0005, 0.01]).unwrap(),
+            Distortion::fromCoefficients("brown-conrady", &[0.8, 0.3, 0.0003, -0.0002, 0.01, 1.0, 0.4, 0.05]).unwrap(),
+            Distortion::fromCoefficients("kannala-brandt", &[0.02, -0.005, 0.001, -0.0002]).unwrap(),
+        ]
+    }
+
+    // Every projection, and the pinhole with every distortion model, seen from a rotated camera
+    fn Cameras() -> Vec<Camera> {
+        let mut cameras = vec![];
+        for projection in Projection::ALL {
+            let distortions = if projection == Projection::Perspective { Distortions() } else { vec![Distortion::None] };
+            for distortion in distortions {
+                let mut camera = Camera::new(Matrix4::identity(), 60.0, 64.0, 48.0);
+                camera.lookAt(&Vector3::new(1.0, 2.0, 3.0), &Vector3::zeros(), &Vector3::y());
+                camera.setProjection(projection);
+                camera.setDistortion(distortion);
+                cameras.push(camera);
+            }
+        }
+        cameras
+    }
+
+    #[test]
+    fn ProjectPointInvertsGenerateRay() {
+        for camera in Cameras() {
+            let mut points = vec![];
+            let mut positions = vec![];
+            for y in 0..12 {
+                for x in 0..16 {
+                    let position = Vector2::new(x as f32 * 4.0 + 1.25, y as f32 * 4.0 + 2.75);
+                    if let Some(ray) = camera.generateRay(position.x, position.y) {
+                        points.push(ray.origin + ray.direction.normalize() * 3.0);
+                        positions.push(position);
+                    }
+                }
+            }
+            assert!(!points.is_empty());
+
+            let batch = camera.projectPoints(&points);
+            for ((point, position), batched) in points.iter().zip(&positions).zip(batch) {
+                let projection = camera.projectPoint(point).unwrap();
+                assert!(projection.visible);
+                assert!(
+                    (projection.pixel - position).norm() < 1e-3,
+                    "{} with {}: {:?} projects to {:?}", camera.projection.name(), camera.distortion.name(), position, projection.pixel
+                );
+                assert_eq!(batched, Some(projection));
+            }
+        }
+    }
+
+    #[test]
+    fn PointsOutsideTheViewAreNotVisible() {
+        let mut camera = Camera::new(Matrix4::identity(), 60.0, 64.0, 48.0);
+        assert!(camera.projectPoint(&Vector3::new(0.0, 0.0, -1.0)).is_none());
+        assert!(!camera.projectPoint(&Vector3::new(10.0, 0.0, 1.0)).unwrap().visible);
+
+        camera.setProjection(Projection::FisheyeEquidistant { fov: 180.0 });
+        assert!(!camera.projectPoint(&Vector3::new(0.0, 1.0, -1.0)).unwrap().visible);
+    }
+
+    #[test]
+    fn EquirectangularPanoramaMatchesEnvironmentLayout() {
+        let mut camera = Camera::new(Matrix4::identity(), 60.0, 64.0, 32.0);
+        camera.setProjection(Projection::Equirectangular);
+
+        for (x, y) in [(0.5, 0.5), (16.0, 8.0), (32.0, 16.0), (40.25, 30.5), (63.5, 31.5)] {
+            let ray = camera.generateRay(x, y).unwrap();
+            let uv = EquirectUv(&ray.direction);
+            assert!((uv - Vector2::new(x / 64.0, y / 32.0)).norm() < 1e-5, "{:?} at {} {}", uv, x, y);
+        }
+    }
+}
@@ -0,0 +1,351 @@
+use std::path::{Path, PathBuf};
+
+use nalgebra::Vector3;
+
+use crate::calibration::{Calibration, CalibrationFormat, LoadCalibration, SaveCalibration};
+use crate::camera::ApertureShape;
+use crate::distortion::Distortion;
+use crate::export::{HasExtension, IsFloatFormat, SaveFloatImage};
+use crate::film::Filter;
+use crate::integrator::IntegratorKind;
+use crate::loader::LoadOptions;
+use crate::projection::Projection;
+use crate::renderer::{QuantizeImageBuffer, Renderer};
+use crate::sampler::SamplerKind;
+use crate::tonemap::{DisplaySettings, Encoding, ToneMapper};
+
+pub const USAGE: &str = "\
+usage: rust-rendering render --scene <demo|scene.toml|model file> --out <image file> [options]
+
+options:
+    --width <pixels>        image width (default from the scene file, else 640)
+    --height <pixels>       image height (default from the scene file, else 480)
+    --size <width>x<height> both at once, such as 1920x1080
+    --spp <samples>         samples per pixel (default 16)
+    --integrator <name>     normals, depth, barycentrics, primitive-id or path (default path)
+    --sampler <name>        independent, stratified, halton, sobol or blue-noise (default sobol)
+    --filter <name>         box, tent, gaussian, mitchell or blackman-harris pixel filter (default box)
+    --aperture <radius>     lens radius in scene units for depth of field (default 0, a pinhole)
+    --blades <count>        polygonal aperture with this many blades (default circular)
+    --focus <distance|auto> distance of the sharp plane, or auto to focus on the image center
+    --projection <name>     perspective, orthographic, fisheye-equidistant, fisheye-equisolid,
+                            equirectangular or cubemap (default perspective)
+    --fisheye-fov <degrees> field of view across the fisheye image circle (default 180)
+    --ortho-height <size>   height of the orthographic view in scene units (default 4)
+    --distortion <model:k>  lens distortion of the perspective projection in OpenCV coefficient
+                            order, brown-conrady:k1,k2,p1,p2[,k3[,k4,k5,k6]],
+                            kannala-brandt:k1,k2,k3,k4 or none (default none)
+    --calibration <file>    OpenCV or ROS YAML calibration with the resolution, intrinsic matrix
+                            and distortion of a real camera, --width and --height rescale it
+    --save-calibration <file>
+                            writes the intrinsics and distortion of the rendered camera
+    --calibration-format <opencv|ros>
+                            layout of --save-calibration (default opencv)
+    --environment <file>    equirectangular .hdr or .exr environment lighting the scene
+    --tonemap <name>        clamp, reinhard, aces or agx (default clamp)
+    --exposure <ev>         exposure adjustment in stops (default 0)
+    --gamma <srgb|value>    output encoding, srgb, a gamma such as 2.2, or 1 for linear (default srgb)
+
+The output format follows the file extension; .exr, .pfm and .hdr keep the unclamped float
+radiance, other formats are tone mapped and encoded. OpenEXR files also get depth, position,
+normal, albedo, geometryId, primitiveId and barycentrics layers, PFM files get them as
+<name>.<layer>.pfm next to the image.";
+
+pub struct RenderArgs {
+    pub scene: String,
+    pub out: PathBuf,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub samplesPerPixel: u32,
+    pub integratorKind: IntegratorKind,
+    pub samplerKind: SamplerKind,
+    pub filter: Filter,
+    pub aperture: Option<f32>,
+    pub blades: Option<u32>,
+    pub focusDistance: Option<f32>,
+    pub autofocus: bool,
+    pub projection: Option<Projection>,
+    pub fisheyeFov: Option<f32>,
+    pub orthoHeight: Option<f32>,
+    pub distortion: Option<Distortion>,
+    pub calibration: Option<PathBuf>,
+    pub saveCalibration: Option<PathBuf>,
+    pub calibrationFormat: CalibrationFormat,
+    pub environment: Option<PathBuf>,
+    pub display: DisplaySettings,
+}
+
+fn ParseDistance(flag: &str, value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(distance) if distance >= 0.0 && distance.is_finite() => Ok(distance),
+        _ => Err(format!("{} expects a non-negative number, got '{}'", flag, value)),
+    }
+}
+
+// model:c1,c2,... with the coefficients in OpenCV order, or just the model name for none
+fn ParseDistortion(value: &str) -> Result<Distortion, String> {
+    let (model, list) = value.split_once(':').unwrap_or((value, ""));
+    let coefficients = list.split(',').filter(|c| !c.trim().is_empty())
+        .map(|c| c.trim().parse::<f32>().ok().filter(|c| c.is_finite()))
+        .collect::<Option<Vec<f32>>>()
+        .ok_or_else(|| format!("--distortion expects numeric coefficients, got '{}'", list))?;
+    Distortion::fromCoefficients(model, &coefficients)
+        .ok_or_else(|| format!("--distortion expects none, brown-conrady with 4, 5 or 8 coefficients or kannala-brandt with 4, got '{}'", value))
+}
+
+fn ParseNumber(flag: &str, value: &str) -> Result<u32, String> {
+    match value.parse::<u32>() {
+        Ok(number) if number > 0 => Ok(number),
+        _ => Err(format!("{} expects a positive integer, got '{}'", flag, value)),
+    }
+}
+
+// Parses the arguments following the "render" subcommand
+pub fn ParseRenderArgs(args: &[String]) -> Result<RenderArgs, String> {
+    let mut scene = None;
+    let mut out = None;
+    let mut renderArgs = RenderArgs {
+        scene: String::new(),
+        out: PathBuf::new(),
+        width: None,
+        height: None,
+        samplesPerPixel: 16,
+        integratorKind: IntegratorKind::PathTracing,
+        samplerKind: SamplerKind::Sobol,
+        filter: Filter::Box,
+        aperture: None,
+        blades: None,
+        focusDistance: None,
+        autofocus: false,
+        projection: None,
+        fisheyeFov: None,
+        orthoHeight: None,
+        distortion: None,
+        calibration: None,
+        saveCalibration: None,
+        calibrationFormat: CalibrationFormat::OpenCv,
+        environment: None,
+        display: DisplaySettings::default(),
+    };
+
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let value = args.next().ok_or_else(|| format!("missing value for {}", flag))?;
+        match flag.as_str() {
+            "--scene" => scene = Some(value.clone()),
+            "--out" => out = Some(PathBuf::from(value)),
+            "--width" => renderArgs.width = Some(ParseNumber(flag, value)?),
+            "--height" => renderArgs.height = Some(ParseNumber(flag, value)?),
+            "--size" => {
+                let (width, height) = value.split_once('x')
+                    .ok_or_else(|| format!("--size expects <width>x<height>, got '{}'", value))?;
+                renderArgs.width = Some(ParseNumber(flag, width)?);
+                renderArgs.height = Some(ParseNumber(flag, height)?);
+            }
+            "--spp" => renderArgs.samplesPerPixel = ParseNumber(flag, value)?,
+            "--integrator" => {
+                renderArgs.integratorKind = IntegratorKind::fromKey(value)
+                    .ok_or_else(|| format!("unknown integrator '{}'", value))?;
+            }
+            "--sampler" => {
+                renderArgs.samplerKind = SamplerKind::fromKey(value)
+                    .ok_or_else(|| format!("unknown sampler '{}'", value))?;
+            }
+            "--filter" => {
+                renderArgs.filter = Filter::fromKey(value)
+                    .ok_or_else(|| format!("unknown filter '{}'", value))?;
+            }
+            "--aperture" => renderArgs.aperture = Some(ParseDistance(flag, value)?),
+            "--blades" => match ParseNumber(flag, value)? {
+                blades if blades >= 3 => renderArgs.blades = Some(blades),
+                _ => return Err(format!("--blades expects at least 3 blades, got '{}'", value)),
+            },
+            "--focus" => match value.as_str() {
+                "auto" => renderArgs.autofocus = true,
+                _ => renderArgs.focusDistance = Some(ParseDistance(flag, value)?),
+            },
+            "--projection" => {
+                renderArgs.projection = Some(Projection::fromKey(value)
+                    .ok_or_else(|| format!("unknown projection '{}'", value))?);
+            }
+            "--fisheye-fov" => match value.parse::<f32>() {
+                Ok(fov) if fov > 0.0 && fov <= 360.0 => renderArgs.fisheyeFov = Some(fov),
+                _ => return Err(format!("--fisheye-fov expects degrees in (0, 360], got '{}'", value)),
+            },
+            "--ortho-height" => match ParseDistance(flag, value)? {
+                height if height > 0.0 => renderArgs.orthoHeight = Some(height),
+                _ => return Err(format!("--ortho-height expects a positive size, got '{}'", value)),
+            },
+            "--distortion" => renderArgs.distortion = Some(ParseDistortion(value)?),
+            "--calibration" => renderArgs.calibration = Some(PathBuf::from(value)),
+            "--save-calibration" => renderArgs.saveCalibration = Some(PathBuf::from(value)),
+            "--calibration-format" => {
+                renderArgs.calibrationFormat = CalibrationFormat::fromKey(value)
+                    .ok_or_else(|| format!("unknown calibration format '{}'", value))?;
+            }
+            "--environment" => renderArgs.environment = Some(PathBuf::from(value)),
+            "--tonemap" => {
+                renderArgs.display.toneMapper = ToneMapper::fromKey(value)
+                    .ok_or_else(|| format!("unknown tone mapper '{}'", value))?;
+            }
+            "--exposure" => {
+                renderArgs.display.exposure = value.parse::<f32>().ok().filter(|ev| ev.is_finite())
+                    .ok_or_else(|| format!("--exposure expects a number, got '{}'", value))?;
+            }
+            "--gamma" => {
+                renderArgs.display.encoding = match value.as_str() {
+                    "srgb" => Encoding::Srgb,
+                    _ => match value.parse::<f32>() {
+                        Ok(1.0) => Encoding::Linear,
+                        Ok(gamma) if gamma > 0.0 && gamma.is_finite() => Encoding::Gamma(gamma),
+                        _ => return Err(format!("--gamma expects 'srgb' or a positive number, got '{}'", value)),
+                    },
+                };
+            }
+            _ => return Err(format!("unknown option '{}'", flag)),
+        }
+    }
+
+    renderArgs.scene = scene.ok_or("missing --scene")?;
+    renderArgs.out = out.ok_or("missing --out")?;
+    Ok(renderArgs)
+}
+
+// Renders a single image without opening a window
+pub fn RenderHeadless(args: &RenderArgs) -> Result<(), String> {
+    let mut renderer = Renderer::new();
+    renderer.camera.setRotation(&Vector3::y_axis(), 180.0_f32.to_radians());
+    renderer.camera.setTranslation(&Vector3::new(0.0, 0.0, 5.0));
+    renderer.setIntegrator(args.integratorKind);
+    renderer.setSampler(args.samplerKind);
+    renderer.setFilter(args.filter);
+
+    let scenePath = Path::new(&args.scene);
+    if args.scene == "demo" {
+        renderer.createDemoScene();
+    } else if !scenePath.is_file() {
+        return Err(format!("scene file '{}' does not exist", args.scene));
+    } else if HasExtension(scenePath, &["toml"]) {
+        renderer.loadSceneFile(scenePath).map_err(|error| error.to_string())?;
+    } else {
+        renderer.loadScene(scenePath, &LoadOptions::default()).map_err(|error| error.to_string())?;
+    }
+
+    // Overrides the environment of a scene file
+    if let Some(environment) = &args.environment {
+        renderer.loadEnvironment(environment, 1.0, 0.0)
+            .map_err(|error| format!("cannot load environment '{}': {}", environment.display(), error))?;
+    }
+
+    // Overrides the camera model of a scene file
+    if let Some(calibration) = &args.calibration {
+        if !LoadCalibration(calibration).map_err(|error| error.to_string())?.apply(&mut renderer.camera) {
+            return Err(format!("calibration '{}' does not describe a valid camera", calibration.display()));
+        }
+    }
+
+    let width = args.width.unwrap_or(renderer.camera.imageWidth as u32);
+    let height = args.height.unwrap_or(renderer.camera.imageHeight as u32);
+    renderer.camera.resize(width as f32, height as f32);
+
+    // Overrides the lens of a scene file
+    if args.aperture.is_some() || args.blades.is_some() {
+        let radius = args.aperture.unwrap_or(renderer.camera.apertureRadius);
+        let shape = match args.blades {
+            Some(blades) => ApertureShape::Polygonal { blades },
+            None => renderer.camera.apertureShape,
+        };
+        renderer.camera.setAperture(radius, shape);
+    }
+
+    // Overrides the projection of a scene file, the parameters apply to whichever one is active
+    let projection = match (args.projection.unwrap_or(renderer.camera.projection), args.fisheyeFov, args.orthoHeight) {
+        (Projection::FisheyeEquidistant { .. }, Some(fov), _) => Projection::FisheyeEquidistant { fov },
+        (Projection::FisheyeEquisolid { .. }, Some(fov), _) => Projection::FisheyeEquisolid { fov },
+        (Projection::Orthographic { .. }, _, Some(height)) => Projection::Orthographic { height },
+        (projection, _, _) => projection,
+    };
+    if projection != renderer.camera.projection {
+        renderer.camera.setProjection(projection);
+    }
+
+    if let Some(distortion) = args.distortion {
+        renderer.camera.setDistortion(distortion);
+    }
+
+    if let Some(distance) = args.focusDistance {
+        renderer.camera.setFocusDistance(distance);
+    }
+    if args.autofocus && renderer.camera.autofocus(&renderer.world).is_none() {
+        eprintln!("warning: autofocus found no surface at the image center");
+    }
+
+    if let Some(path) = &args.saveCalibration {
+        SaveCalibration(path, &Calibration::fromCamera(&renderer.camera), args.calibrationFormat)
+            .map_err(|error| error.to_string())?;
+    }
+
+    let hdrImageBuffer = renderer.renderHdrImageBuffer(args.samplesPerPixel);
+
+    let result = if IsFloatFormat(&args.out) {
+        let aovs = renderer.renderAovs();
+        SaveFloatImage(&args.out, &hdrImageBuffer, Some(&aovs)).map_err(|error| error.to_string())
+    } else {
+        QuantizeImageBuffer(&hdrImageBuffer, &args.display).save(&args.out).map_err(|error| error.to_string())
+    };
+    result.map_err(|error| format!("failed to write '{}': {}", args.out.display(), error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn Parse(line: &str) -> Result<RenderArgs, String> {
+        let args: Vec<String> = line.split_whitespace().map(String::from).collect();
+        ParseRenderArgs(&args)
+    }
+
+    #[test]
+    fn ParsesDefaultsAndRequiredFlags() {
+        let args = Parse("--scene demo --out frame.png").unwrap();
+        assert_eq!(args.scene, "demo");
+        assert_eq!(args.out, PathBuf::from("frame.png"));
+        assert_eq!((args.width, args.height, args.samplesPerPixel), (None, None, 16));
+        assert_eq!(args.display.encoding, Encoding::Srgb);
+
+        assert_eq!(Parse("--out frame.png").err().unwrap(), "missing --scene");
+        assert_eq!(Parse("--scene demo").err().unwrap(), "missing --out");
+    }
+
+    #[test]
+    fn RejectsMissingValuesAndUnknownFlags() {
+        assert_eq!(Parse("--scene demo --out").err().unwrap(), "missing value for --out");
+        assert_eq!(Parse("--scene demo --out frame.png --frobnicate 3").err().unwrap(), "unknown option '--frobnicate'");
+        assert!(Parse("--scene demo --out frame.png --spp 0").is_err());
+        assert!(Parse("--scene demo --out frame.png --integrator raymarch").is_err());
+    }
+
+    #[test]
+    fn ParsesSizes() {
+        let args = Parse("--scene demo --out frame.png --size 1920x1080").unwrap();
+        assert_eq!((args.width, args.height), (Some(1920), Some(1080)));
+
+        let args = Parse("--scene demo --out frame.png --width 320 --height 200").unwrap();
+        assert_eq!((args.width, args.height), (Some(320), Some(200)));
+
+        for size in ["1920", "1920x", "x1080", "0x1080", "1920x-5", "axb"] {
+            assert!(Parse(&format!("--scene demo --out frame.png --size {}", size)).is_err(), "{}", size);
+        }
+    }
+
+    #[test]
+    fn ParsesGamma() {
+        let gamma = |value: &str| Parse(&format!("--scene demo --out frame.png --gamma {}", value)).map(|args| args.display.encoding);
+        assert_eq!(gamma("1"), Ok(Encoding::Linear));
+        assert_eq!(gamma("1.0"), Ok(Encoding::Linear));
+        assert_eq!(gamma("2.2"), Ok(Encoding::Gamma(2.2)));
+        assert_eq!(gamma("srgb"), Ok(Encoding::Srgb));
+        assert!(gamma("0").is_err());
+        assert!(gamma("inf").is_err());
+    }
+}
@@ -0,0 +1,215 @@
+use nalgebra::{Vector2, Vector3};
+
+// Undistortion is solved iteratively, these bound the work and the accepted residual in
+// normalized image coordinates
+const MAX_ITERATIONS: u32 = 20;
+const TOLERANCE: f32 = 1e-6;
+const MAX_RESIDUAL: f32 = 1e-3;
+
+// Lens distortion of a calibrated camera, mapping ideal pinhole coordinates (x / z, y / z) to the
+// normalized coordinates the intrinsic matrix turns into pixels. Coefficients follow OpenCV.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Distortion {
+    None,
+    // Radial k1..k6 as the rational model (1 + k1 r^2 + k2 r^4 + k3 r^6) / (1 + k4 r^2 + k5 r^4 + k6 r^6)
+    // and tangential p1, p2
+    BrownConrady { k: [f32; 6], p: [f32; 2] },
+    // Equidistant fisheye, theta_d = theta (1 + k1 theta^2 + k2 theta^4 + k3 theta^6 + k4 theta^8)
+    KannalaBrandt { k: [f32; 4] },
+}
+
+impl Distortion {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Distortion::None => "None",
+            Distortion::BrownConrady { .. } => "Brown-Conrady",
+            Distortion::KannalaBrandt { .. } => "Kannala-Brandt",
+        }
+    }
+
+    // Command line spelling
+    pub fn key(&self) -> &'static str {
+        match self {
+            Distortion::None => "none",
+            Distortion::BrownConrady { .. } => "brown-conrady",
+            Distortion::KannalaBrandt { .. } => "kannala-brandt",
+        }
+    }
+
+    // Model from its key and coefficients in OpenCV order: k1, k2, p1, p2[, k3[, k4, k5, k6]]
+    // for Brown-Conrady and k1, k2, k3, k4 for Kannala-Brandt. None for other counts.
+    pub fn fromCoefficients(key: &str, coefficients: &[f32]) -> Option<Self> {
+        let c = |index: usize| coefficients.get(index).copied().unwrap_or(0.0);
+        match (key, coefficients.len()) {
+            ("none", 0) => Some(Distortion::None),
+            ("brown-conrady", 4 | 5 | 8) => Some(Distortion::BrownConrady {
+                k: [c(0), c(1), c(4), c(5), c(6), c(7)],
+                p: [c(2), c(3)],
+            }),
+            ("kannala-brandt", 4) => Some(Distortion::KannalaBrandt { k: [c(0), c(1), c(2), c(3)] }),
+            _ => None,
+        }
+    }
+
+    // Coefficients in the order fromCoefficients takes them, as short as the model allows
+    pub fn coefficients(&self) -> Vec<f32> {
+        match *self {
+            Distortion::None => Vec::new(),
+            Distortion::BrownConrady { k, p } => {
+                let mut coefficients = vec![k[0], k[1], p[0], p[1], k[2], k[3], k[4], k[5]];
+                let length = if k[3..].iter().any(|&k| k != 0.0) { 8 } else if k[2] != 0.0 { 5 } else { 4 };
+                coefficients.truncate(length);
+                coefficients
+            }
+            Distortion::KannalaBrandt { k } => k.to_vec(),
+        }
+    }
+
+    // Distorted normalized coordinates of a camera space direction, None for directions the model
+    // cannot see, behind the camera for Brown-Conrady and straight behind it for Kannala-Brandt
+    pub fn distort(&self, direction: &Vector3<f32>) -> Option<Vector2<f32>> {
+        match *self {
+            Distortion::None | Distortion::BrownConrady { .. } if direction.z <= 0.0 => None,
+            Distortion::None => Some(direction.xy() / direction.z),
+            Distortion::BrownConrady { k, p } => Some(BrownConrady(&(direction.xy() / direction.z), &k, &p)),
+            Distortion::KannalaBrandt { k } => {
+                // Every azimuth meets on the axis behind the camera
+                let radius = direction.xy().norm();
+                if radius == 0.0 {
+                    return if direction.z > 0.0 { Some(Vector2::zeros()) } else { None };
+                }
+                let theta = radius.atan2(direction.z);
+                Some(direction.xy() * (KannalaBrandt(theta, &k) / radius))
+            }
+        }
+    }
+
+    // Unit camera space direction of distorted normalized coordinates, None where the iteration
+    // finds no undistorted point, such as beyond the edge of strong barrel distortion
+    pub fn undistort(&self, distorted: &Vector2<f32>) -> Option<Vector3<f32>> {
+        match *self {
+            Distortion::None => Some(Vector3::new(distorted.x, distorted.y, 1.0).normalize()),
+            Distortion::BrownConrady { k, p } => {
+                // Fixed point iteration of OpenCV's undistortPoints
+                let mut point = *distorted;
+                for _ in 0..MAX_ITERATIONS {
+                    let r2 = point.norm_squared();
+                    let radial = (1.0 + r2 * (k[0] + r2 * (k[1] + r2 * k[2]))) / (1.0 + r2 * (k[3] + r2 * (k[4] + r2 * k[5])));
+                    if radial.is_nan() || radial <= 0.0 {
+                        return None;
+                    }
+                    let next = (distorted - Tangential(&point, &p)) / radial;
+                    let step = (next - point).norm();
+                    point = next;
+                    if step < TOLERANCE {
+                        break;
+                    }
+                }
+
+                if !point.iter().all(|value| value.is_finite()) || (BrownConrady(&point, &k, &p) - distorted).norm() > MAX_RESIDUAL {
+                    return None;
+                }
+                Some(Vector3::new(point.x, point.y, 1.0).normalize())
+            }
+            Distortion::KannalaBrandt { k } => {
+                let thetaDistorted = distorted.norm();
+                if thetaDistorted == 0.0 {
+                    return Some(Vector3::z());
+                }
+
+                // Newton's method on theta_d(theta) = thetaDistorted
+                let mut theta = thetaDistorted;
+                for _ in 0..MAX_ITERATIONS {
+                    let t2 = theta * theta;
+                    let derivative = 1.0 + t2 * (3.0 * k[0] + t2 * (5.0 * k[1] + t2 * (7.0 * k[2] + t2 * 9.0 * k[3])));
+                    if derivative.abs() < 1e-8 {
+                        return None;
+                    }
+                    let step = (KannalaBrandt(theta, &k) - thetaDistorted) / derivative;
+                    theta -= step;
+                    if step.abs() < TOLERANCE {
+                        break;
+                    }
+                }
+
+                if !(0.0..=std::f32::consts::PI).contains(&theta) || (KannalaBrandt(theta, &k) - thetaDistorted).abs() > MAX_RESIDUAL {
+                    return None;
+                }
+                let azimuth = distorted / thetaDistorted;
+                Some(Vector3::new(theta.sin() * azimuth.x, theta.sin() * azimuth.y, theta.cos()))
+            }
+        }
+    }
+}
+
+fn Tangential(point: &Vector2<f32>, p: &[f32; 2]) -> Vector2<f32> {
+    let (x, y) = (point.x, point.y);
+    let r2 = point.norm_squared();
+    Vector2::new(2.0 * p[0] * x * y + p[1] * (r2 + 2.0 * x * x), p[0] * (r2 + 2.0 * y * y) + 2.0 * p[1] * x * y)
+}
+
+fn BrownConrady(point: &Vector2<f32>, k: &[f32; 6], p: &[f32; 2]) -> Vector2<f32> {
+    let r2 = point.norm_squared();
+    let radial = (1.0 + r2 * (k[0] + r2 * (k[1] + r2 * k[2]))) / (1.0 + r2 * (k[3] + r2 * (k[4] + r2 * k[5])));
+    point * radial + Tangential(point, p)
+}
+
+fn KannalaBrandt(theta: f32, k: &[f32; 4]) -> f32 {
+    let t2 = theta * theta;
+    theta * (1.0 + t2 * (k[0] + t2 * (k[1] + t2 * (k[2] + t2 * k[3]))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn Models() -> Vec<Distortion> {
+        vec![
+            Distortion::None,
+            Distortion::fromCoefficients("brown-conrady", &[-0.28, 0.07, 0.0008, -0.0005]).unwrap(),
+            Distortion::fromCoefficients("brown-conrady", &[0.12, -0.05, -0.001, 0.002, 0.01]).unwrap(),
+            Distortion::fromCoefficients("brown-conrady", &[1.2, 0.4, 0.0003, -0.0002, 0.01, 1.5, 0.6, 0.05]).unwrap(),
+            Distortion::fromCoefficients("kannala-brandt", &[0.02, -0.005, 0.001, -0.0002]).unwrap(),
+            Distortion::fromCoefficients("kannala-brandt", &[-0.01, 0.0, 0.0, 0.0]).unwrap(),
+        ]
+    }
+
+    // Directions up to 40 degrees off the axis, within the range every model above is invertible
+    fn Directions() -> impl Iterator<Item=Vector3<f32>> {
+        (0..=8).flat_map(|i| (0..12).map(move |j| {
+            let theta = (i as f32 * 5.0).to_radians();
+            let phi = (j as f32 * 30.0).to_radians();
+            Vector3::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos())
+        }))
+    }
+
+    #[test]
+    fn UndistortInvertsDistort() {
+        for distortion in Models() {
+            for direction in Directions() {
+                let distorted = distortion.distort(&direction).unwrap();
+                let undistorted = distortion.undistort(&distorted).unwrap();
+                assert!((undistorted - direction).norm() < 1e-4, "{:?}: {:?} became {:?}", distortion, direction, undistorted);
+                assert!((distortion.distort(&undistorted).unwrap() - distorted).norm() < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn FisheyeSeesBehindTheCameraOnlyOffTheAxis() {
+        let fisheye = Distortion::fromCoefficients("kannala-brandt", &[0.0; 4]).unwrap();
+        assert!(fisheye.distort(&Vector3::new(0.0, 0.0, -1.0)).is_none());
+        assert!(fisheye.distort(&Vector3::zeros()).is_none());
+
+        let sideways = fisheye.distort(&Vector3::new(1.0, 0.0, -0.1)).unwrap();
+        assert!(sideways.x > std::f32::consts::FRAC_PI_2);
+        assert!(Distortion::None.distort(&Vector3::new(1.0, 0.0, -0.1)).is_none());
+    }
+
+    #[test]
+    fn CoefficientsRoundTrip() {
+        for distortion in Models() {
+            assert_eq!(Distortion::fromCoefficients(distortion.key(), &distortion.coefficients()), Some(distortion));
+        }
+    }
+}
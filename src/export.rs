@@ -0,0 +1,297 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use exr::prelude::{AnyChannel, AnyChannels, Encoding, FlatSamples, Image, Layer, LayerAttributes, SmallVec, Vec2, WritableImage};
+use image::{ImageError, Rgb32FImage};
+use nalgebra::Vector3;
+
+use crate::aov::{AovBuffer, AovBuffers, NO_OBJECT};
+
+#[derive(Debug)]
+pub enum ExportError {
+    Io(io::Error),
+    Exr(exr::error::Error),
+    Image(ImageError),
+    UnsupportedFormat(PathBuf),
+    SizeMismatch,
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportError::Io(error) => write!(f, "{}", error),
+            ExportError::Exr(error) => write!(f, "{}", error),
+            ExportError::Image(error) => write!(f, "{}", error),
+            ExportError::UnsupportedFormat(path) => write!(f, "'{}' is not an .exr, .pfm or .hdr file", path.display()),
+            ExportError::SizeMismatch => write!(f, "render outputs have different sizes"),
+        }
+    }
+}
+
+impl std::error::Error for ExportError {}
+
+impl From<io::Error> for ExportError {
+    fn from(error: io::Error) -> Self {
+        ExportError::Io(error)
+    }
+}
+
+impl From<exr::error::Error> for ExportError {
+    fn from(error: exr::error::Error) -> Self {
+        ExportError::Exr(error)
+    }
+}
+
+pub fn HasExtension(path: &Path, extensions: &[&str]) -> bool {
+    let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or("");
+    extensions.contains(&extension.to_ascii_lowercase().as_str())
+}
+
+// Formats that keep the unclamped linear radiance
+pub fn IsFloatFormat(path: &Path) -> bool {
+    HasExtension(path, &["exr", "pfm", "hdr"])
+}
+
+// Writes the linear framebuffer by file extension. OpenEXR files get the AOVs as extra layers,
+// PFM files get them as sidecar files next to the beauty image, Radiance HDR has no room for them.
+pub fn SaveFloatImage(path: &Path, beauty: &Rgb32FImage, aovs: Option<&AovBuffers>) -> Result<(), ExportError> {
+    if HasExtension(path, &["exr"]) {
+        WriteExr(path, beauty, aovs)
+    } else if HasExtension(path, &["pfm"]) {
+        WritePfm(path, beauty)?;
+        match aovs {
+            Some(aovs) => WritePfmAovs(path, aovs),
+            None => Ok(()),
+        }
+    } else if HasExtension(path, &["hdr"]) {
+        beauty.save(path).map_err(ExportError::Image)
+    } else {
+        Err(ExportError::UnsupportedFormat(path.to_path_buf()))
+    }
+}
+
+fn FloatChannel(name: &str, samples: Vec<f32>) -> AnyChannel<FlatSamples> {
+    AnyChannel::new(name, FlatSamples::F32(samples))
+}
+
+fn VectorChannels(layer: &str, components: [&str; 3], buffer: &AovBuffer<Vector3<f32>>) -> Vec<AnyChannel<FlatSamples>> {
+    components.iter().enumerate().map(|(index, component)| {
+        FloatChannel(&format!("{}.{}", layer, component), buffer.data.iter().map(|value| value[index]).collect())
+    }).collect()
+}
+
+// Single part OpenEXR with the beauty as R, G, B and every AOV as a layer of prefixed channels:
+// depth.Z, position.X/Y/Z, normal.X/Y/Z, albedo.R/G/B, geometryId.id, primitiveId.id and
+// barycentrics.U/V. The ids are stored as unsigned integers.
+pub fn WriteExr(path: &Path, beauty: &Rgb32FImage, aovs: Option<&AovBuffers>) -> Result<(), ExportError> {
+    let (width, height) = beauty.dimensions();
+    let component = |index: usize| beauty.pixels().map(|pixel| pixel.0[index]).collect::<Vec<f32>>();
+
+    let mut channels = vec![
+        FloatChannel("R", component(0)),
+        FloatChannel("G", component(1)),
+        FloatChannel("B", component(2)),
+    ];
+
+    if let Some(aovs) = aovs {
+        if (aovs.width(), aovs.height()) != (width, height) {
+            return Err(ExportError::SizeMismatch);
+        }
+
+        channels.push(FloatChannel("depth.Z", aovs.depth.data.clone()));
+        channels.extend(VectorChannels("position", ["X", "Y", "Z"], &aovs.position));
+        channels.extend(VectorChannels("normal", ["X", "Y", "Z"], &aovs.normal));
+        channels.extend(VectorChannels("albedo", ["R", "G", "B"], &aovs.albedo));
+        channels.push(AnyChannel::new("geometryId.id", FlatSamples::U32(aovs.geometryId.data.clone())));
+        channels.push(AnyChannel::new("primitiveId.id", FlatSamples::U32(aovs.primitiveId.data.clone())));
+        channels.push(FloatChannel("barycentrics.U", aovs.barycentrics.data.iter().map(|uv| uv.x).collect()));
+        channels.push(FloatChannel("barycentrics.V", aovs.barycentrics.data.iter().map(|uv| uv.y).collect()));
+    }
+
+    let layer = Layer::new(
+        Vec2(width as usize, height as usize),
+        LayerAttributes::named("render"),
+        Encoding::FAST_LOSSLESS,
+        AnyChannels::sort(SmallVec::from_vec(channels)),
+    );
+
+    Image::from_layer(layer).write().to_file(path)?;
+    Ok(())
+}
+
+// Portable float map: a text header followed by little endian floats, rows stored bottom to top
+fn WritePfmData(path: &Path, width: u32, height: u32, channels: usize, values: &[f32]) -> Result<(), ExportError> {
+    let mut file = BufWriter::new(File::create(path)?);
+    let magic = if channels == 3 { "PF" } else { "Pf" };
+    write!(file, "{}\n{} {}\n-1.0\n", magic, width, height)?;
+
+    let rowLength = width as usize * channels;
+    for row in values.chunks_exact(rowLength.max(1)).rev() {
+        for value in row {
+            file.write_all(&value.to_le_bytes())?;
+        }
+    }
+
+    file.flush()?;
+    Ok(())
+}
+
+pub fn WritePfm(path: &Path, image: &Rgb32FImage) -> Result<(), ExportError> {
+    WritePfmData(path, image.width(), image.height(), 3, image.as_raw())
+}
+
+// <name>.pfm gets a <name>.<aov>.pfm file for every AOV. PFM only stores one or three floats per
+// pixel, so ids become floats with -1 on background and barycentrics get all three weights.
+pub fn WritePfmAovs(path: &Path, aovs: &AovBuffers) -> Result<(), ExportError> {
+    let stem = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("render");
+    let sidecar = |aov: &str| path.with_file_name(format!("{}.{}.pfm", stem, aov));
+    let (width, height) = (aovs.width(), aovs.height());
+
+    let vectors = |buffer: &AovBuffer<Vector3<f32>>| buffer.data.iter().flat_map(|value| [value.x, value.y, value.z]).collect::<Vec<f32>>();
+    let ids = |buffer: &AovBuffer<u32>| buffer.data.iter().map(|&id| if id == NO_OBJECT { -1.0 } else { id as f32 }).collect::<Vec<f32>>();
+    let barycentrics: Vec<f32> = aovs.barycentrics.data.iter().flat_map(|uv| [1.0 - uv.x - uv.y, uv.x, uv.y]).collect();
+
+    WritePfmData(&sidecar("depth"), width, height, 1, &aovs.depth.data)?;
+    WritePfmData(&sidecar("position"), width, height, 3, &vectors(&aovs.position))?;
+    WritePfmData(&sidecar("normal"), width, height, 3, &vectors(&aovs.normal))?;
+    WritePfmData(&sidecar("albedo"), width, height, 3, &vectors(&aovs.albedo))?;
+    WritePfmData(&sidecar("geometryId"), width, height, 1, &ids(&aovs.geometryId))?;
+    WritePfmData(&sidecar("primitiveId"), width, height, 1, &ids(&aovs.primitiveId))?;
+    WritePfmData(&sidecar("barycentrics"), width, height, 3, &barycentrics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Vector2;
+
+    use crate::aov::AovSample;
+
+    const WIDTH: u32 = 5;
+    const HEIGHT: u32 = 3;
+
+    fn Beauty() -> Rgb32FImage {
+        Rgb32FImage::from_fn(WIDTH, HEIGHT, |x, y| image::Rgb([x as f32, y as f32 + 0.5, -1.5 * (x + 7 * y) as f32]))
+    }
+
+    // Every pixel but the last one hits something, with values that tell the pixels apart
+    fn Aovs() -> AovBuffers {
+        let mut aovs = AovBuffers::new(WIDTH, HEIGHT);
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                if (x, y) == (WIDTH - 1, HEIGHT - 1) {
+                    continue;
+                }
+                let i = (y * WIDTH + x) as f32;
+                aovs.set(x, y, &AovSample {
+                    depth: 1.0 + i,
+                    position: Vector3::new(i, 2.0 * i, -i),
+                    normal: Vector3::new(0.0, 1.0, i),
+                    albedo: Vector3::new(0.25, 0.5, i / 16.0),
+                    geometryId: x,
+                    primitiveId: 100 + y,
+                    barycentrics: Vector2::new(0.125 * x as f32, 0.25 * y as f32),
+                });
+            }
+        }
+        aovs
+    }
+
+    fn TempDir(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("export-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    // Returns the size, channel count and the rows flipped back to top to bottom order
+    fn ReadPfm(path: &Path) -> (u32, u32, usize, Vec<f32>) {
+        let bytes = std::fs::read(path).unwrap();
+        let mut lines = bytes.splitn(4, |&byte| byte == b'\n');
+        let mut text = || std::str::from_utf8(lines.next().unwrap()).unwrap().to_string();
+        let channels = match text().as_str() {
+            "PF" => 3,
+            "Pf" => 1,
+            magic => panic!("bad magic '{}'", magic),
+        };
+        let size = text();
+        let (width, height) = size.split_once(' ').unwrap();
+        let (width, height): (u32, u32) = (width.parse().unwrap(), height.parse().unwrap());
+        assert_eq!(text(), "-1.0");
+
+        let data = lines.next().unwrap();
+        let values: Vec<f32> = data.chunks_exact(4).map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap())).collect();
+        assert_eq!(values.len(), width as usize * height as usize * channels);
+        let rows: Vec<&[f32]> = values.chunks_exact(width as usize * channels).rev().collect();
+        (width, height, channels, rows.concat())
+    }
+
+    #[test]
+    fn ExrRoundTrip() {
+        let (beauty, aovs) = (Beauty(), Aovs());
+        let path = TempDir("exr").join("render.exr");
+        SaveFloatImage(&path, &beauty, Some(&aovs)).unwrap();
+
+        let image = exr::prelude::read_all_flat_layers_from_file(&path).unwrap();
+        assert_eq!(image.layer_data.len(), 1);
+        let layer = &image.layer_data[0];
+        assert_eq!(layer.size, Vec2(WIDTH as usize, HEIGHT as usize));
+
+        let channel = |name: &str| &layer.channel_data.list.iter().find(|channel| channel.name.to_string() == name)
+            .unwrap_or_else(|| panic!("no channel '{}'", name)).sample_data;
+        let floats = |name: &str| match channel(name) {
+            FlatSamples::F32(samples) => samples.clone(),
+            _ => panic!("'{}' is not f32", name),
+        };
+        let ids = |name: &str| match channel(name) {
+            FlatSamples::U32(samples) => samples.clone(),
+            _ => panic!("'{}' is not u32", name),
+        };
+
+        let mut names: Vec<String> = layer.channel_data.list.iter().map(|channel| channel.name.to_string()).collect();
+        names.sort();
+        assert_eq!(names, [
+            "B", "G", "R", "albedo.B", "albedo.G", "albedo.R", "barycentrics.U", "barycentrics.V", "depth.Z", "geometryId.id",
+            "normal.X", "normal.Y", "normal.Z", "position.X", "position.Y", "position.Z", "primitiveId.id",
+        ]);
+
+        for (index, name) in ["R", "G", "B"].iter().enumerate() {
+            assert_eq!(floats(name), beauty.pixels().map(|pixel| pixel.0[index]).collect::<Vec<f32>>());
+        }
+        assert_eq!(floats("depth.Z"), aovs.depth.data);
+        assert_eq!(floats("position.Y"), aovs.position.data.iter().map(|value| value.y).collect::<Vec<f32>>());
+        assert_eq!(floats("normal.Z"), aovs.normal.data.iter().map(|value| value.z).collect::<Vec<f32>>());
+        assert_eq!(floats("albedo.B"), aovs.albedo.data.iter().map(|value| value.z).collect::<Vec<f32>>());
+        assert_eq!(floats("barycentrics.V"), aovs.barycentrics.data.iter().map(|uv| uv.y).collect::<Vec<f32>>());
+        assert_eq!(ids("geometryId.id"), aovs.geometryId.data);
+        assert_eq!(ids("primitiveId.id"), aovs.primitiveId.data);
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn PfmRoundTrip() {
+        let (beauty, aovs) = (Beauty(), Aovs());
+        let directory = TempDir("pfm");
+        SaveFloatImage(&directory.join("render.pfm"), &beauty, Some(&aovs)).unwrap();
+
+        assert_eq!(ReadPfm(&directory.join("render.pfm")), (WIDTH, HEIGHT, 3, beauty.as_raw().clone()));
+        assert_eq!(ReadPfm(&directory.join("render.depth.pfm")), (WIDTH, HEIGHT, 1, aovs.depth.data.clone()));
+
+        let (_, _, channels, positions) = ReadPfm(&directory.join("render.position.pfm"));
+        assert_eq!(channels, 3);
+        assert_eq!(positions, aovs.position.data.iter().flat_map(|value| [value.x, value.y, value.z]).collect::<Vec<f32>>());
+
+        // Background ids come back as -1
+        let (_, _, _, geometryIds) = ReadPfm(&directory.join("render.geometryId.pfm"));
+        assert_eq!(geometryIds.last(), Some(&-1.0));
+        assert_eq!(&geometryIds[..6], &[0.0, 1.0, 2.0, 3.0, 4.0, 0.0]);
+
+        let (_, _, _, barycentrics) = ReadPfm(&directory.join("render.barycentrics.pfm"));
+        let uv = aovs.barycentrics.data[7];
+        assert_eq!(&barycentrics[21..24], &[1.0 - uv.x - uv.y, uv.x, uv.y]);
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,262 @@
+use std::f32::consts::PI;
+
+use image::{Rgb, Rgb32FImage};
+use nalgebra::Vector3;
+
+use crate::tiles::Tile;
+
+// Pixel reconstruction filters, all separable and centered on the pixel center
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Filter {
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+    BlackmanHarris,
+}
+
+impl Filter {
+    pub const ALL: [Filter; 5] = [
+        Filter::Box,
+        Filter::Tent,
+        Filter::Gaussian,
+        Filter::Mitchell,
+        Filter::BlackmanHarris,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Filter::Box => "Box",
+            Filter::Tent => "Tent",
+            Filter::Gaussian => "Gaussian",
+            Filter::Mitchell => "Mitchell-Netravali",
+            Filter::BlackmanHarris => "Blackman-Harris",
+        }
+    }
+
+    // Command line spelling
+    pub fn key(&self) -> &'static str {
+        match self {
+            Filter::Box => "box",
+            Filter::Tent => "tent",
+            Filter::Gaussian => "gaussian",
+            Filter::Mitchell => "mitchell",
+            Filter::BlackmanHarris => "blackman-harris",
+        }
+    }
+
+    pub fn fromKey(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|filter| filter.key() == key)
+    }
+
+    // Support half width in pixels
+    pub fn radius(&self) -> f32 {
+        match self {
+            Filter::Box => 0.5,
+            Filter::Tent => 1.0,
+            Filter::Gaussian => 1.5,
+            Filter::Mitchell => 2.0,
+            Filter::BlackmanHarris => 2.0,
+        }
+    }
+
+    // Weight of a sample at offset (dx, dy) from a pixel center, normalized to integrate to one
+    pub fn evaluate(&self, dx: f32, dy: f32) -> f32 {
+        self.evaluate1D(dx) * self.evaluate1D(dy)
+    }
+
+    fn evaluate1D(&self, x: f32) -> f32 {
+        // The box is half-open like the pixel footprint [x, x + 1), so a sample on the border
+        // between two pixels only counts for the one it lies in
+        let radius = self.radius();
+        if x.abs() > radius || (*self == Filter::Box && x == -radius) {
+            return 0.0;
+        }
+        let x = x.abs();
+
+        match self {
+            Filter::Box => 1.0,
+            Filter::Tent => 1.0 - x / radius,
+            Filter::Gaussian => {
+                // Shifted down to reach zero at the radius
+                const SIGMA: f32 = 0.5;
+                let gaussian = |x: f32| (-x * x / (2.0 * SIGMA * SIGMA)).exp();
+                // Integral of the shifted curve over [-radius, radius]
+                const NORMALIZATION: f32 = 1.2166035;
+                (gaussian(x) - gaussian(radius)).max(0.0) / NORMALIZATION
+            }
+            Filter::Mitchell => Mitchell1D(2.0 * x / radius, 1.0 / 3.0, 1.0 / 3.0),
+            Filter::BlackmanHarris => {
+                let t = PI * x / radius;
+                // The cosines cancel over the support, leaving the constant term
+                let window = 0.35875 + 0.48829 * t.cos() + 0.14128 * (2.0 * t).cos() + 0.01168 * (3.0 * t).cos();
+                window / (0.35875 * 2.0 * radius)
+            }
+        }
+    }
+}
+
+// Cubic of Mitchell and Netravali 1988 over |x| in [0, 2]
+fn Mitchell1D(x: f32, b: f32, c: f32) -> f32 {
+    let value = if x < 1.0 {
+        (12.0 - 9.0 * b - 6.0 * c) * x * x * x + (-18.0 + 12.0 * b + 6.0 * c) * x * x + (6.0 - 2.0 * b)
+    } else {
+        (-b - 6.0 * c) * x * x * x + (6.0 * b + 30.0 * c) * x * x + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)
+    };
+    value / 6.0
+}
+
+// Filter weighted radiance sums over a rectangle of the image. Samples are splatted into every
+// pixel within the filter radius and the image is their weighted average.
+#[derive(Clone, Debug)]
+pub struct Film {
+    x: u32,
+    y: u32,
+    pub width: u32,
+    pub height: u32,
+    sums: Vec<Vector3<f32>>,
+    weights: Vec<f32>,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self::region(0, 0, width, height)
+    }
+
+    fn region(x: u32, y: u32, width: u32, height: u32) -> Self {
+        let count = (width * height) as usize;
+        Self { x, y, width, height, sums: vec![Vector3::zeros(); count], weights: vec![0.0; count] }
+    }
+
+    // Film for the samples of one tile, grown by the filter radius so samples near the tile
+    // border can reach the neighboring pixels. Tile films are merged after rendering.
+    pub fn forTile(tile: &Tile, filter: &Filter, imageWidth: u32, imageHeight: u32) -> Self {
+        let border = (filter.radius() - 0.5).ceil().max(0.0) as u32;
+        let x = tile.x.saturating_sub(border);
+        let y = tile.y.saturating_sub(border);
+        let right = (tile.x + tile.width + border).min(imageWidth);
+        let bottom = (tile.y + tile.height + border).min(imageHeight);
+        Self::region(x, y, right - x, bottom - y)
+    }
+
+    // Adds a sample at a continuous image position, pixel (x, y) spans [x, x + 1) x [y, y + 1)
+    pub fn addSample(&mut self, filter: &Filter, imageX: f32, imageY: f32, radiance: &Vector3<f32>) {
+        // Samples that went wrong would poison every pixel they touch
+        if !radiance.iter().all(|value| value.is_finite()) {
+            return;
+        }
+
+        let radius = filter.radius();
+        let (centerX, centerY) = (imageX - 0.5, imageY - 0.5);
+        let x0 = ((centerX - radius).ceil() as i64).max(self.x as i64);
+        let y0 = ((centerY - radius).ceil() as i64).max(self.y as i64);
+        let x1 = ((centerX + radius).floor() as i64).min((self.x + self.width) as i64 - 1);
+        let y1 = ((centerY + radius).floor() as i64).min((self.y + self.height) as i64 - 1);
+
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                let weight = filter.evaluate(x as f32 - centerX, y as f32 - centerY);
+                if weight == 0.0 {
+                    continue;
+                }
+                let i = ((y - self.y as i64) * self.width as i64 + (x - self.x as i64)) as usize;
+                self.sums[i] += radiance * weight;
+                self.weights[i] += weight;
+            }
+        }
+    }
+
+    // Adds the sums of another film, which may cover any part of this one
+    pub fn merge(&mut self, other: &Film) {
+        for y in 0..other.height {
+            for x in 0..other.width {
+                let (imageX, imageY) = (other.x + x, other.y + y);
+                if imageX < self.x || imageY < self.y || imageX >= self.x + self.width || imageY >= self.y + self.height {
+                    continue;
+                }
+                let source = (y * other.width + x) as usize;
+                let target = ((imageY - self.y) * self.width + (imageX - self.x)) as usize;
+                self.sums[target] += other.sums[source];
+                self.weights[target] += other.weights[source];
+            }
+        }
+    }
+
+    // Reconstructed radiance of a pixel in image coordinates, black where no weight landed
+    pub fn pixel(&self, x: u32, y: u32) -> Vector3<f32> {
+        let i = ((y - self.y) * self.width + (x - self.x)) as usize;
+        let weight = self.weights[i];
+        if weight.abs() < 1e-8 { Vector3::zeros() } else { self.sums[i] / weight }
+    }
+
+    pub fn image(&self) -> Rgb32FImage {
+        Rgb32FImage::from_fn(self.width, self.height, |x, y| {
+            let radiance = self.pixel(self.x + x, self.y + y);
+            Rgb([radiance.x, radiance.y, radiance.z])
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::Rng;
+    use crate::tiles::GenerateTiles;
+
+    #[test]
+    fn FiltersIntegrateToOne() {
+        // Midpoint rule over the support
+        for filter in Filter::ALL {
+            let steps = 400;
+            let step = 2.0 * filter.radius() / steps as f32;
+            let mut integral = 0.0;
+            for i in 0..steps {
+                for j in 0..steps {
+                    let dx = -filter.radius() + (i as f32 + 0.5) * step;
+                    let dy = -filter.radius() + (j as f32 + 0.5) * step;
+                    integral += filter.evaluate(dx, dy) as f64 * (step * step) as f64;
+                }
+            }
+            assert!((integral - 1.0).abs() < 1e-3, "{} integrates to {}", filter.name(), integral);
+        }
+    }
+
+    #[test]
+    fn BoxSamplesOnPixelBordersCountOnce() {
+        let mut film = Film::new(4, 1);
+        film.addSample(&Filter::Box, 2.0, 0.5, &Vector3::new(1.0, 0.0, 0.0));
+        film.addSample(&Filter::Box, 1.5, 0.5, &Vector3::new(0.0, 1.0, 0.0));
+        assert_eq!(film.pixel(1, 0), Vector3::new(0.0, 1.0, 0.0));
+        assert_eq!(film.pixel(2, 0), Vector3::new(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn MergedTilesMatchTheWholeImage() {
+        let (width, height) = (23, 17);
+        for filter in Filter::ALL {
+            let mut whole = Film::new(width, height);
+            let mut merged = Film::new(width, height);
+            let mut rng = Rng::new(3, 5);
+
+            for tile in GenerateTiles(width, height, 8) {
+                let mut film = Film::forTile(&tile, &filter, width, height);
+                for (x, y) in tile.pixels() {
+                    for _ in 0..4 {
+                        let offset = rng.next2D();
+                        let radiance = Vector3::new(rng.nextF32(), rng.nextF32(), rng.nextF32());
+                        film.addSample(&filter, x as f32 + offset.x, y as f32 + offset.y, &radiance);
+                        whole.addSample(&filter, x as f32 + offset.x, y as f32 + offset.y, &radiance);
+                    }
+                }
+                merged.merge(&film);
+            }
+
+            for y in 0..height {
+                for x in 0..width {
+                    let (expected, actual) = (whole.pixel(x, y), merged.pixel(x, y));
+                    assert!((expected - actual).norm() < 1e-5, "{} at ({}, {}): {:?} {:?}", filter.name(), x, y, expected, actual);
+                }
+            }
+        }
+    }
+}
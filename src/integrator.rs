@@ -0,0 +1,336 @@
+use nalgebra::Vector3;
+
+use crate::intersection::{Ray, MAX_PASS_THROUGH};
+use crate::sampler::Sampler;
+use crate::sampling::{PowerHeuristic, Rng, ToLocal, ToWorld};
+use crate::world::World;
+
+// Computes the radiance arriving at the camera along a single ray
+pub trait Integrator: Send + Sync {
+    fn Li(&self, world: &World, ray: Ray, sampler: &mut dyn Sampler) -> Vector3<f32>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IntegratorKind {
+    Normals,
+    Depth,
+    Barycentrics,
+    PrimitiveId,
+    PathTracing,
+}
+
+impl IntegratorKind {
+    pub const ALL: [IntegratorKind; 5] = [
+        IntegratorKind::Normals,
+        IntegratorKind::Depth,
+        IntegratorKind::Barycentrics,
+        IntegratorKind::PrimitiveId,
+        IntegratorKind::PathTracing,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            IntegratorKind::Normals => "Normals",
+            IntegratorKind::Depth => "Depth",
+            IntegratorKind::Barycentrics => "Barycentrics",
+            IntegratorKind::PrimitiveId => "Primitive ID",
+            IntegratorKind::PathTracing => "Path tracing",
+        }
+    }
+
+    // Command line spelling, e.g. "primitive-id"
+    pub fn key(&self) -> &'static str {
+        match self {
+            IntegratorKind::Normals => "normals",
+            IntegratorKind::Depth => "depth",
+            IntegratorKind::Barycentrics => "barycentrics",
+            IntegratorKind::PrimitiveId => "primitive-id",
+            IntegratorKind::PathTracing => "path",
+        }
+    }
+
+    pub fn fromKey(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.key() == key)
+    }
+
+    pub fn create(&self) -> Box<dyn Integrator> {
+        match self {
+            IntegratorKind::Normals => Box::new(NormalIntegrator),
+            IntegratorKind::Depth => Box::new(DepthIntegrator::new()),
+            IntegratorKind::Barycentrics => Box::new(BarycentricIntegrator),
+            IntegratorKind::PrimitiveId => Box::new(PrimitiveIdIntegrator),
+            IntegratorKind::PathTracing => Box::new(PathTracer::new()),
+        }
+    }
+}
+
+// Outward facing shading normal of the first hit, remapped from [-1, 1] to [0, 1]
+pub struct NormalIntegrator;
+
+impl Integrator for NormalIntegrator {
+    fn Li(&self, world: &World, ray: Ray, _sampler: &mut dyn Sampler) -> Vector3<f32> {
+        match world.intersect(&ray.origin, &ray.direction) {
+            Some(hit) => {
+                let normal = if hit.frontFacing { hit.shadingNormal } else { -hit.shadingNormal };
+                (normal + Vector3::new(1.0, 1.0, 1.0)) * 0.5
+            }
+            None => Vector3::zeros(),
+        }
+    }
+}
+
+// Distance along the ray to the first hit, scaled so that farDistance maps to white
+pub struct DepthIntegrator {
+    pub farDistance: f32,
+}
+
+impl DepthIntegrator {
+    pub fn new() -> Self {
+        Self { farDistance: 10.0 }
+    }
+}
+
+impl Integrator for DepthIntegrator {
+    fn Li(&self, world: &World, ray: Ray, _sampler: &mut dyn Sampler) -> Vector3<f32> {
+        match world.intersect(&ray.origin, &ray.direction.normalize()) {
+            Some(hit) => Vector3::repeat(hit.distance / self.farDistance),
+            None => Vector3::zeros(),
+        }
+    }
+}
+
+// Hit barycentrics (1 - u - v, u, v) as color, the UVs of the primitive parameterization
+pub struct BarycentricIntegrator;
+
+impl Integrator for BarycentricIntegrator {
+    fn Li(&self, world: &World, ray: Ray, _sampler: &mut dyn Sampler) -> Vector3<f32> {
+        match world.intersect(&ray.origin, &ray.direction) {
+            Some(hit) => Vector3::new((1.0 - hit.u - hit.v).max(0.0), hit.u, hit.v),
+            None => Vector3::zeros(),
+        }
+    }
+}
+
+// Stable false color per (geometry, primitive) pair
+pub struct PrimitiveIdIntegrator;
+
+impl Integrator for PrimitiveIdIntegrator {
+    fn Li(&self, world: &World, ray: Ray, _sampler: &mut dyn Sampler) -> Vector3<f32> {
+        match world.intersect(&ray.origin, &ray.direction) {
+            Some(hit) => FalseColor(hit.geometryId, hit.primitiveId),
+            None => Vector3::zeros(),
+        }
+    }
+}
+
+pub fn FalseColor(geometryId: u32, primitiveId: u32) -> Vector3<f32> {
+    let mut rng = Rng::new(primitiveId as u64, geometryId as u64);
+    Vector3::new(rng.nextF32(), rng.nextF32(), rng.nextF32()) * 0.8 + Vector3::repeat(0.2)
+}
+
+// Unidirectional path tracer with Russian roulette termination. Direct light is estimated at every
+// bounce with a shadow ray to one light, combined with emitters hit by the bounce rays through
+// multiple importance sampling.
+pub struct PathTracer {
+    pub maxDepth: u32,
+    pub rouletteDepth: u32,     // bounces before Russian roulette kicks in
+    pub skyRadiance: Option<Vector3<f32>>,     // for rays leaving a world without environment, None picks one
+}
+
+impl PathTracer {
+    pub fn new() -> Self {
+        Self {
+            maxDepth: 16,
+            rouletteDepth: 3,
+            skyRadiance: None,
+        }
+    }
+
+    // A white sky keeps scenes without lights visible, once lights exist it would add light they
+    // do not account for
+    fn escapedRadiance(&self, world: &World) -> Vector3<f32> {
+        match self.skyRadiance {
+            Some(radiance) => radiance,
+            None if world.lights().is_empty() => Vector3::new(1.0, 1.0, 1.0),
+            None => Vector3::zeros(),
+        }
+    }
+}
+
+impl Integrator for PathTracer {
+    fn Li(&self, world: &World, ray: Ray, sampler: &mut dyn Sampler) -> Vector3<f32> {
+        let mut radiance = Vector3::<f32>::zeros();
+        let mut throughput = Vector3::<f32>::new(1.0, 1.0, 1.0);
+        let mut origin = ray.origin;
+        let mut direction = ray.direction.normalize();
+        let mut spreadAngle = ray.spreadAngle;
+        let mut coneWidth = 0.0;
+
+        // Position and solid angle density of the last bounce, None for camera rays
+        let mut lastBounce: Option<(Vector3<f32>, f32)> = None;
+
+        for depth in 0..self.maxDepth {
+            // Cut-out surfaces let the path continue straight through with probability 1 - opacity.
+            // Passing through is not a bounce, so stacked layers do not use up the path depth.
+            let mut passThroughs = 0;
+            let surface = loop {
+                let hit = match world.intersect(&origin, &direction) {
+                    Some(hit) => hit,
+                    None => break None,
+                };
+
+                // Ray cone width at the hit drives the texture level of detail
+                coneWidth += spreadAngle * hit.distance;
+                let uvFootprint = hit.uvFootprint(coneWidth, &direction);
+
+                let opacity = world.material(hit.geometryId).opacityAt(&hit, uvFootprint);
+                if opacity < 1.0 && passThroughs < MAX_PASS_THROUGH && sampler.next1D() >= opacity {
+                    origin = hit.spawnOrigin(&direction);
+                    passThroughs += 1;
+                    continue;
+                }
+                break Some((hit, uvFootprint));
+            };
+
+            let (hit, uvFootprint) = match surface {
+                Some(surface) => surface,
+                None => {
+                    match world.environment() {
+                        Some(environment) => {
+                            let weight = match lastBounce {
+                                Some((_, bouncePdf)) => PowerHeuristic(bouncePdf, environment.escapedPdf(&direction) * world.lightPickProbability()),
+                                None => 1.0,
+                            };
+                            radiance += throughput.component_mul(&environment.escapedRadiance(&direction)) * weight;
+                        }
+                        None => radiance += throughput.component_mul(&self.escapedRadiance(world)),
+                    }
+                    break;
+                }
+            };
+            let material = world.material(hit.geometryId);
+
+            if hit.frontFacing && material.isEmissive() {
+                let weight = match (lastBounce, world.lightOf(hit.geometryId)) {
+                    (Some((bouncePosition, bouncePdf)), Some(light)) => {
+                        PowerHeuristic(bouncePdf, light.pdf(&bouncePosition, &hit) * world.lightPickProbability())
+                    }
+                    _ => 1.0,
+                };
+                radiance += throughput.component_mul(&material.emission) * weight;
+            }
+
+            // Shading happens in the frame of the (normal mapped) shading normal, which faces the
+            // incoming ray like the geometric normal
+            let normal = material.perturbNormal(&hit, &hit.shadingNormal, uvFootprint);
+            let bsdf = material.bsdfAt(&hit, uvFootprint);
+            let wo = ToLocal(&-direction, &normal);
+
+            // Next event estimation towards one light, pointless for mirror-like surfaces
+            let lightChoice = if bsdf.isSpecular() { None } else { world.sampleLight(sampler.next1D()) };
+            if let Some((light, pickProbability)) = lightChoice {
+                let u = sampler.next2D();
+                if let Some(sample) = light.sample(&hit.position, &u) {
+                    let wi = ToLocal(&sample.direction, &normal);
+                    let lightPdf = sample.pdf * pickProbability;
+
+                    // Directions must lie on the same side of both normals to avoid light leaks
+                    let consistent = (sample.direction.dot(&hit.geometricNormal) > 0.0) == (wi.z > 0.0);
+                    let f = bsdf.evaluate(&wo, &wi);
+                    if consistent && lightPdf > 0.0 && f.max() > 0.0 {
+                        let shadowOrigin = hit.spawnOrigin(&sample.direction);
+                        let transmittance = world.transmittance(&shadowOrigin, &sample.direction, sample.distance * (1.0 - 1e-3));
+                        if transmittance > 0.0 {
+                            let weight = if light.isDelta() { 1.0 } else { PowerHeuristic(lightPdf, bsdf.pdf(&wo, &wi)) };
+                            let contribution = f.component_mul(&sample.radiance) * (transmittance * wi.z.abs() * weight / lightPdf);
+                            radiance += throughput.component_mul(&contribution);
+                        }
+                    }
+                }
+            }
+
+            let sample = match bsdf.sample(&wo, &sampler.next2D(), sampler.next1D()) {
+                Some(sample) => sample,
+                None => break,
+            };
+            direction = ToWorld(&sample.direction, &normal).normalize();
+            if (direction.dot(&hit.geometricNormal) > 0.0) != (sample.direction.z > 0.0) {
+                break;
+            }
+            origin = hit.spawnOrigin(&direction);
+            throughput = throughput.component_mul(&sample.weight);
+
+            // Specular bounces cannot be reached by light sampling, so emitters they hit count fully
+            lastBounce = if sample.isSpecular { None } else { Some((hit.position, sample.pdf)) };
+
+            // Rough bounces spread the cone widely, textures are then read from coarse levels
+            if !sample.isSpecular {
+                spreadAngle = std::f32::consts::FRAC_PI_4;
+            }
+
+            if depth + 1 >= self.rouletteDepth {
+                let survival = throughput.max().min(0.95);
+                if survival <= 0.0 || sampler.next1D() >= survival {
+                    break;
+                }
+                throughput /= survival;
+            }
+        }
+
+        radiance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::light::PointLight;
+    use crate::material::Material;
+    use crate::sampler::IndependentSampler;
+    use crate::world::{TriangleMesh, DEFAULT_MATERIAL};
+
+    fn Quad(z: f32) -> TriangleMesh {
+        let positions = vec![
+            Vector3::new(-1.0, -1.0, z),
+            Vector3::new(1.0, -1.0, z),
+            Vector3::new(1.0, 1.0, z),
+            Vector3::new(-1.0, 1.0, z),
+        ];
+        TriangleMesh::new(positions, vec![(0, 1, 2), (0, 2, 3)])
+    }
+
+    // Direct light on a receiver at z = 0 from a point light above it, shadowed by a cut-out quad
+    // halfway between them. The camera ray starts below the cut-out.
+    fn ReceivedRadiance(opacity: f32) -> f32 {
+        let mut world = World::new();
+        world.addTriangleMesh(Quad(0.0), DEFAULT_MATERIAL);
+        let cutOut = world.addMaterial(Material { opacity, ..Material::default() });
+        world.addTriangleMesh(Quad(1.0), cutOut);
+        world.addLight(Box::new(PointLight { position: Vector3::new(0.0, 0.0, 2.0), intensity: Vector3::new(4.0, 4.0, 4.0) }));
+        world.commit();
+
+        let integrator = PathTracer { maxDepth: 1, ..PathTracer::new() };
+        let ray = Ray::new(Vector3::new(0.0, 0.0, 0.5), Vector3::new(0.0, 0.0, -1.0), 0.0);
+        integrator.Li(&world, ray, &mut IndependentSampler::new()).x
+    }
+
+    #[test]
+    fn CutOutsLetLightThrough() {
+        let unshadowed = ReceivedRadiance(0.0);
+        assert!(unshadowed > 0.0);
+        assert!((ReceivedRadiance(0.25) - 0.75 * unshadowed).abs() < 1e-4 * unshadowed);
+        assert_eq!(ReceivedRadiance(1.0), 0.0);
+    }
+
+    #[test]
+    fn SkyIsBlackOnceLightsExist() {
+        let mut world = World::new();
+        world.commit();
+        let ray = Ray::new(Vector3::zeros(), Vector3::z(), 0.0);
+        assert_eq!(PathTracer::new().Li(&world, ray, &mut IndependentSampler::new()), Vector3::new(1.0, 1.0, 1.0));
+
+        world.addLight(Box::new(PointLight { position: Vector3::new(0.0, 0.0, -1.0), intensity: Vector3::new(1.0, 1.0, 1.0) }));
+        let ray = Ray::new(Vector3::zeros(), Vector3::z(), 0.0);
+        assert_eq!(PathTracer::new().Li(&world, ray, &mut IndependentSampler::new()), Vector3::zeros());
+    }
+}
@@ -0,0 +1,96 @@
+use nalgebra::{Vector2, Vector3};
+use rust_embree::{CastRay, EmbreeScene};
+
+// Offset applied to secondary ray origins to avoid self intersections
+pub const RAY_EPSILON: f32 = 1e-4;
+
+// Bound on the cut-out surfaces a ray steps through before the next one counts as opaque
+pub const MAX_PASS_THROUGH: u32 = 64;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Ray {
+    pub origin: Vector3<f32>,
+    pub direction: Vector3<f32>,
+    pub spreadAngle: f32,   // cone angle covered by the ray, used to pick texture mip levels
+}
+
+impl Ray {
+    pub fn new(origin: Vector3<f32>, direction: Vector3<f32>, spreadAngle: f32) -> Self {
+        Self { origin, direction, spreadAngle }
+    }
+}
+
+pub struct SurfaceHit {
+    pub distance: f32,
+    pub position: Vector3<f32>,
+    pub geometricNormal: Vector3<f32>,     // normalized, facing against the incoming ray
+    pub shadingNormal: Vector3<f32>,       // interpolated vertex normal on the same side, geometricNormal without one
+    pub frontFacing: bool,
+    pub u: f32,
+    pub v: f32,
+    pub geometryId: u32,
+    pub primitiveId: u32,
+    pub uv: Vector2<f32>,                   // texture coordinates, the barycentrics when the mesh has none
+    pub uvAreaRatio: Option<f32>,           // 0.5 * log2(uv area / world area) of the hit triangle
+    pub tangent: Option<Vector3<f32>>,      // dp/du in world space, for normal mapping
+}
+
+impl SurfaceHit {
+    // Log2 of the uv-space footprint of a ray cone of the given width, see "Texture Level of
+    // Detail Strategies for Real-Time Ray Tracing" (Akenine-Moller et al. 2019)
+    pub fn uvFootprint(&self, coneWidth: f32, direction: &Vector3<f32>) -> f32 {
+        match self.uvAreaRatio {
+            Some(uvAreaRatio) => {
+                let cosine = direction.normalize().dot(&self.geometricNormal).abs().max(1e-4);
+                uvAreaRatio + (coneWidth / cosine).max(1e-12).log2()
+            }
+            None => f32::NEG_INFINITY,
+        }
+    }
+
+    // Origin for a ray leaving the surface towards the given direction
+    pub fn spawnOrigin(&self, direction: &Vector3<f32>) -> Vector3<f32> {
+        let offset = self.geometricNormal * RAY_EPSILON * (1.0 + self.position.amax());
+        if direction.dot(&self.geometricNormal) >= 0.0 {
+            self.position + offset
+        } else {
+            self.position - offset
+        }
+    }
+}
+
+pub fn Intersect(scene: &EmbreeScene, origin: &Vector3<f32>, direction: &Vector3<f32>) -> Option<SurfaceHit> {
+    let rayHit = CastRay(scene, (origin.x, origin.y, origin.z, direction.x, direction.y, direction.z))?;
+    let hit = rayHit.hit;
+
+    let normal = Vector3::new(hit.Ng_x, hit.Ng_y, hit.Ng_z);
+    let normal = normal.try_normalize(0.0).unwrap_or_else(|| -direction.normalize());
+    let frontFacing = normal.dot(direction) < 0.0;
+    let normal = if frontFacing { normal } else { -normal };
+
+    let distance = rayHit.ray.tfar;
+
+    Some(SurfaceHit {
+        distance,
+        position: origin + direction * distance,
+        geometricNormal: normal,
+        shadingNormal: normal,
+        frontFacing,
+        u: hit.u,
+        v: hit.v,
+        geometryId: hit.geomID,
+        primitiveId: hit.primID,
+        uv: Vector2::new(hit.u, hit.v),
+        uvAreaRatio: None,
+        tangent: None,
+    })
+}
+
+// Whether anything blocks the ray before maxDistance. The embree wrapper only exposes closest hit
+// queries, so this casts a regular ray and compares the hit distance.
+pub fn Occluded(scene: &EmbreeScene, origin: &Vector3<f32>, direction: &Vector3<f32>, maxDistance: f32) -> bool {
+    match CastRay(scene, (origin.x, origin.y, origin.z, direction.x, direction.y, direction.z)) {
+        Some(rayHit) => rayHit.ray.tfar < maxDistance,
+        None => false,
+    }
+}
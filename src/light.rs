@@ -0,0 +1,497 @@
+use std::f32::consts::PI;
+use std::path::Path;
+
+use image::{ImageError, Rgb32FImage};
+use nalgebra::{Vector2, Vector3};
+
+use crate::intersection::SurfaceHit;
+use crate::sampling::{Distribution2D, ToWorld};
+
+// Incident light at a shading point, towards the light
+pub struct LightSample {
+    pub direction: Vector3<f32>,    // normalized
+    pub distance: f32,              // to the sampled point, infinite for directional lights
+    pub radiance: Vector3<f32>,     // arriving radiance, or irradiance for delta lights
+    pub pdf: f32,                   // solid angle density, 1 for delta lights
+}
+
+pub trait Light: Send + Sync {
+    // Samples the light as seen from position, None when it cannot contribute there
+    fn sample(&self, position: &Vector3<f32>, u: &Vector2<f32>) -> Option<LightSample>;
+
+    // Solid angle density with which sample() from origin would have produced the given hit on
+    // this light. Always 0 for delta lights, which cannot be hit.
+    fn pdf(&self, _origin: &Vector3<f32>, _hit: &SurfaceHit) -> f32 {
+        0.0
+    }
+
+    fn isDelta(&self) -> bool {
+        false
+    }
+
+    // Radiance arriving along rays that leave the scene in direction, only infinite lights have any
+    fn escapedRadiance(&self, _direction: &Vector3<f32>) -> Vector3<f32> {
+        Vector3::zeros()
+    }
+
+    // Solid angle density with which sample() produces an escaping direction
+    fn escapedPdf(&self, _direction: &Vector3<f32>) -> f32 {
+        0.0
+    }
+}
+
+pub struct PointLight {
+    pub position: Vector3<f32>,
+    pub intensity: Vector3<f32>,
+}
+
+impl Light for PointLight {
+    fn sample(&self, position: &Vector3<f32>, _u: &Vector2<f32>) -> Option<LightSample> {
+        let toLight = self.position - position;
+        let distanceSquared = toLight.norm_squared();
+        if distanceSquared <= 0.0 {
+            return None;
+        }
+
+        let distance = distanceSquared.sqrt();
+        Some(LightSample {
+            direction: toLight / distance,
+            distance,
+            radiance: self.intensity / distanceSquared,
+            pdf: 1.0,
+        })
+    }
+
+    fn isDelta(&self) -> bool {
+        true
+    }
+}
+
+pub struct DirectionalLight {
+    pub direction: Vector3<f32>,    // direction the light travels in, normalized
+    pub irradiance: Vector3<f32>,
+}
+
+impl Light for DirectionalLight {
+    fn sample(&self, _position: &Vector3<f32>, _u: &Vector2<f32>) -> Option<LightSample> {
+        Some(LightSample {
+            direction: -self.direction,
+            distance: f32::INFINITY,
+            radiance: self.irradiance,
+            pdf: 1.0,
+        })
+    }
+
+    fn isDelta(&self) -> bool {
+        true
+    }
+}
+
+pub struct SpotLight {
+    pub position: Vector3<f32>,
+    pub direction: Vector3<f32>,    // cone axis, normalized
+    pub intensity: Vector3<f32>,
+    pub cosInnerAngle: f32,         // full intensity inside the inner cone
+    pub cosOuterAngle: f32,         // no light outside the outer cone
+}
+
+impl SpotLight {
+    // Smooth falloff between the outer and inner cone
+    fn falloff(&self, cosine: f32) -> f32 {
+        if cosine >= self.cosInnerAngle {
+            return 1.0;
+        }
+        let t = ((cosine - self.cosOuterAngle) / (self.cosInnerAngle - self.cosOuterAngle).max(1e-6)).clamp(0.0, 1.0);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+impl Light for SpotLight {
+    fn sample(&self, position: &Vector3<f32>, _u: &Vector2<f32>) -> Option<LightSample> {
+        let toLight = self.position - position;
+        let distanceSquared = toLight.norm_squared();
+        if distanceSquared <= 0.0 {
+            return None;
+        }
+
+        let distance = distanceSquared.sqrt();
+        let direction = toLight / distance;
+        let falloff = self.falloff(self.direction.dot(&-direction));
+        if falloff <= 0.0 {
+            return None;
+        }
+
+        Some(LightSample {
+            direction,
+            distance,
+            radiance: self.intensity * falloff / distanceSquared,
+            pdf: 1.0,
+        })
+    }
+
+    fn isDelta(&self) -> bool {
+        true
+    }
+}
+
+// Emissive sphere, sampled uniformly within the cone it subtends
+pub struct SphereLight {
+    pub center: Vector3<f32>,
+    pub radius: f32,
+    pub emission: Vector3<f32>,
+}
+
+impl SphereLight {
+    fn cosConeAngle(&self, position: &Vector3<f32>) -> Option<f32> {
+        let distanceSquared = (self.center - position).norm_squared();
+        let radiusSquared = self.radius * self.radius;
+        if distanceSquared <= radiusSquared {
+            return None;
+        }
+        Some((1.0 - radiusSquared / distanceSquared).max(0.0).sqrt())
+    }
+}
+
+impl Light for SphereLight {
+    fn sample(&self, position: &Vector3<f32>, u: &Vector2<f32>) -> Option<LightSample> {
+        let cosMax = self.cosConeAngle(position)?;
+        let toCenter = self.center - position;
+        let axis = toCenter.normalize();
+
+        let cosTheta = 1.0 - u.x * (1.0 - cosMax);
+        let sinTheta = (1.0 - cosTheta * cosTheta).max(0.0).sqrt();
+        let phi = 2.0 * PI * u.y;
+        let direction = ToWorld(&Vector3::new(sinTheta * phi.cos(), sinTheta * phi.sin(), cosTheta), &axis).normalize();
+
+        // Nearest intersection of the sampled direction with the sphere
+        let b = direction.dot(&toCenter);
+        let c = toCenter.norm_squared() - self.radius * self.radius;
+        let distance = b - (b * b - c).max(0.0).sqrt();
+
+        Some(LightSample {
+            direction,
+            distance,
+            radiance: self.emission,
+            pdf: 1.0 / (2.0 * PI * (1.0 - cosMax)).max(1e-12),
+        })
+    }
+
+    fn pdf(&self, origin: &Vector3<f32>, hit: &SurfaceHit) -> f32 {
+        match self.cosConeAngle(origin) {
+            Some(cosMax) if hit.frontFacing => 1.0 / (2.0 * PI * (1.0 - cosMax)).max(1e-12),
+            _ => 0.0,
+        }
+    }
+}
+
+// Emissive triangle mesh in world space, sampled uniformly by area. Only the front side, as
+// given by the winding order, emits.
+pub struct MeshLight {
+    triangles: Vec<[Vector3<f32>; 3]>,
+    cumulativeAreas: Vec<f32>,
+    pub emission: Vector3<f32>,
+}
+
+impl MeshLight {
+    pub fn new(triangles: Vec<[Vector3<f32>; 3]>, emission: Vector3<f32>) -> Self {
+        let mut total = 0.0;
+        let cumulativeAreas = triangles.iter().map(|[a, b, c]| {
+            total += 0.5 * (b - a).cross(&(c - a)).norm();
+            total
+        }).collect();
+
+        Self { triangles, cumulativeAreas, emission }
+    }
+
+    pub fn area(&self) -> f32 {
+        self.cumulativeAreas.last().copied().unwrap_or(0.0)
+    }
+}
+
+impl Light for MeshLight {
+    fn sample(&self, position: &Vector3<f32>, u: &Vector2<f32>) -> Option<LightSample> {
+        let area = self.area();
+        if area <= 0.0 {
+            return None;
+        }
+
+        // Pick a triangle proportionally to its area and reuse u.x within it
+        let target = u.x * area;
+        let index = self.cumulativeAreas.partition_point(|&cumulative| cumulative <= target).min(self.triangles.len() - 1);
+        let start = if index == 0 { 0.0 } else { self.cumulativeAreas[index - 1] };
+        let triangleArea = self.cumulativeAreas[index] - start;
+        let ux = ((target - start) / triangleArea.max(1e-12)).clamp(0.0, 1.0);
+
+        let [a, b, c] = &self.triangles[index];
+        let root = ux.sqrt();
+        let point = a * (1.0 - root) + b * (root * (1.0 - u.y)) + c * (root * u.y);
+        let normal = (b - a).cross(&(c - a)).try_normalize(1e-12)?;
+
+        let toLight = point - position;
+        let distance = toLight.norm();
+        if distance <= 0.0 {
+            return None;
+        }
+        let direction = toLight / distance;
+        let cosine = -normal.dot(&direction);
+        if cosine <= 0.0 {
+            return None;
+        }
+
+        Some(LightSample {
+            direction,
+            distance,
+            radiance: self.emission,
+            pdf: distance * distance / (area * cosine),
+        })
+    }
+
+    fn pdf(&self, origin: &Vector3<f32>, hit: &SurfaceHit) -> f32 {
+        if !hit.frontFacing {
+            return 0.0;
+        }
+        let toLight = hit.position - origin;
+        let distanceSquared = toLight.norm_squared();
+        let cosine = hit.geometricNormal.dot(&-toLight.normalize());
+        if cosine <= 0.0 {
+            return 0.0;
+        }
+        distanceSquared / (self.area() * cosine).max(1e-12)
+    }
+}
+
+// Latitude-longitude mapping with +y up: u = 0 faces -z and grows towards +x, v = 0 is straight up
+pub fn EquirectDirection(uv: &Vector2<f32>) -> Vector3<f32> {
+    let theta = uv.y * PI;
+    let phi = uv.x * 2.0 * PI;
+    Vector3::new(theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos())
+}
+
+pub fn EquirectUv(direction: &Vector3<f32>) -> Vector2<f32> {
+    let direction = direction.normalize();
+    let phi = direction.x.atan2(-direction.z);
+    let u = (phi / (2.0 * PI)).rem_euclid(1.0);
+    let v = direction.y.clamp(-1.0, 1.0).acos() / PI;
+    Vector2::new(u, v)
+}
+
+// Equirectangular HDR image surrounding the scene, importance sampled by luminance
+pub struct EnvironmentLight {
+    image: Rgb32FImage,
+    distribution: Distribution2D,
+    pub intensity: f32,
+    rotation: f32,      // radians around +y
+}
+
+impl EnvironmentLight {
+    // Any float format the image crate reads, typically Radiance .hdr or OpenEXR
+    pub fn load(path: &Path, intensity: f32, rotationDegrees: f32) -> Result<Self, ImageError> {
+        let image = image::open(path)?.to_rgb32f();
+        Ok(Self::fromImage(image, intensity, rotationDegrees))
+    }
+
+    pub fn fromImage(image: Rgb32FImage, intensity: f32, rotationDegrees: f32) -> Self {
+        let (width, height) = (image.width() as usize, image.height() as usize);
+
+        // Rows near the poles cover less solid angle
+        let mut weights = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let sinTheta = ((y as f32 + 0.5) / height as f32 * PI).sin();
+            for x in 0..width {
+                let [r, g, b] = image.get_pixel(x as u32, y as u32).0;
+                let luminance = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+                weights.push(if luminance.is_finite() { luminance.max(0.0) * sinTheta } else { 0.0 });
+            }
+        }
+
+        Self {
+            distribution: Distribution2D::new(&weights, width, height),
+            image,
+            intensity,
+            rotation: rotationDegrees.to_radians(),
+        }
+    }
+
+    fn toLocal(&self, direction: &Vector3<f32>) -> Vector3<f32> {
+        let (sin, cos) = self.rotation.sin_cos();
+        Vector3::new(cos * direction.x - sin * direction.z, direction.y, sin * direction.x + cos * direction.z)
+    }
+
+    fn toWorld(&self, direction: &Vector3<f32>) -> Vector3<f32> {
+        let (sin, cos) = self.rotation.sin_cos();
+        Vector3::new(cos * direction.x + sin * direction.z, direction.y, -sin * direction.x + cos * direction.z)
+    }
+
+    // Nearest texel, matching the piecewise constant sampling density
+    fn lookup(&self, uv: &Vector2<f32>) -> Vector3<f32> {
+        let x = ((uv.x * self.image.width() as f32) as u32).min(self.image.width() - 1);
+        let y = ((uv.y * self.image.height() as f32) as u32).min(self.image.height() - 1);
+        let [r, g, b] = self.image.get_pixel(x, y).0;
+        Vector3::new(r, g, b) * self.intensity
+    }
+
+    // Converts a density over the image to one over solid angle
+    fn solidAnglePdf(uvPdf: f32, uv: &Vector2<f32>) -> f32 {
+        let sinTheta = (uv.y * PI).sin();
+        if sinTheta <= 0.0 { 0.0 } else { uvPdf / (2.0 * PI * PI * sinTheta) }
+    }
+}
+
+impl Light for EnvironmentLight {
+    fn sample(&self, _position: &Vector3<f32>, u: &Vector2<f32>) -> Option<LightSample> {
+        let (uv, uvPdf) = self.distribution.sample(u);
+        let pdf = Self::solidAnglePdf(uvPdf, &uv);
+        if pdf <= 0.0 {
+            return None;
+        }
+
+        Some(LightSample {
+            direction: self.toWorld(&EquirectDirection(&uv)),
+            distance: f32::INFINITY,
+            radiance: self.lookup(&uv),
+            pdf,
+        })
+    }
+
+    fn escapedRadiance(&self, direction: &Vector3<f32>) -> Vector3<f32> {
+        self.lookup(&EquirectUv(&self.toLocal(direction)))
+    }
+
+    fn escapedPdf(&self, direction: &Vector3<f32>) -> f32 {
+        let uv = EquirectUv(&self.toLocal(direction));
+        Self::solidAnglePdf(self.distribution.pdf(&uv), &uv)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Material;
+    use crate::sampling::Rng;
+    use crate::world::{TriangleMesh, World};
+
+    // Samples every area light of the world from origin, traces the sampled direction and checks
+    // that the light's pdf for the hit agrees with the sample
+    fn CheckSampledPdfs(world: &World, origin: &Vector3<f32>) {
+        let mut rng = Rng::new(13, 17);
+        for light in world.lights() {
+            let mut checked = 0;
+            for _ in 0..1000 {
+                let sample = match light.sample(origin, &rng.next2D()) {
+                    Some(sample) => sample,
+                    None => continue,
+                };
+                let hit = world.intersect(origin, &sample.direction).unwrap();
+                assert!((hit.distance - sample.distance).abs() < 1e-3 * sample.distance, "{} {}", hit.distance, sample.distance);
+
+                let pdf = light.pdf(origin, &hit);
+                assert!((pdf - sample.pdf).abs() <= 1e-3 * sample.pdf, "{} {}", pdf, sample.pdf);
+                checked += 1;
+            }
+            assert!(checked > 900);
+        }
+    }
+
+    fn Emitter(world: &mut World) -> usize {
+        world.addMaterial(Material { emission: Vector3::new(1.0, 1.0, 1.0), ..Material::default() })
+    }
+
+    // Bright spot on a vertical gradient, so that the sampling density varies across the image
+    fn Environment() -> EnvironmentLight {
+        let image = Rgb32FImage::from_fn(32, 16, |x, y| {
+            let value = if (x, y) == (7, 4) { 50.0 } else { 0.1 + y as f32 * 0.05 };
+            image::Rgb([value, value * 0.5, value * 0.25])
+        });
+        EnvironmentLight::fromImage(image, 2.0, 30.0)
+    }
+
+    #[test]
+    fn EquirectMappingsInvert() {
+        for i in 0..16 {
+            for j in 1..16 {
+                let uv = Vector2::new((i as f32 + 0.3) / 16.0, j as f32 / 16.0);
+                let direction = EquirectDirection(&uv);
+                assert!((direction.norm() - 1.0).abs() < 1e-5);
+                assert!((EquirectUv(&direction) - uv).norm() < 1e-5, "{:?} {:?}", uv, EquirectUv(&direction));
+                assert!((EquirectDirection(&EquirectUv(&(direction * 3.0))) - direction).norm() < 1e-5);
+            }
+        }
+        assert!((EquirectDirection(&Vector2::new(0.0, 0.5)) + Vector3::z()).norm() < 1e-6);
+        assert!((EquirectDirection(&Vector2::new(0.25, 0.5)) - Vector3::x()).norm() < 1e-6);
+        assert!((EquirectDirection(&Vector2::new(0.5, 0.0)) - Vector3::y()).norm() < 1e-6);
+    }
+
+    #[test]
+    fn EnvironmentSamplesMatchPdf() {
+        let environment = Environment();
+        let mut rng = Rng::new(19, 23);
+        for _ in 0..2000 {
+            let sample = environment.sample(&Vector3::zeros(), &rng.next2D()).unwrap();
+            let pdf = environment.escapedPdf(&sample.direction);
+            assert!((pdf - sample.pdf).abs() <= 1e-3 * sample.pdf, "{} {}", pdf, sample.pdf);
+            assert!((environment.escapedRadiance(&sample.direction) - sample.radiance).norm() <= 1e-4 * sample.radiance.norm());
+        }
+    }
+
+    #[test]
+    fn EnvironmentPdfIntegratesToOne() {
+        // Midpoint rule over the sphere, uniform in z and the azimuth
+        let environment = Environment();
+        let (rows, columns) = (1024, 2048);
+        let mut total = 0.0;
+        for i in 0..rows {
+            let z = 1.0 - 2.0 * (i as f32 + 0.5) / rows as f32;
+            let radius = (1.0 - z * z).sqrt();
+            for j in 0..columns {
+                let phi = 2.0 * PI * (j as f32 + 0.5) / columns as f32;
+                total += environment.escapedPdf(&Vector3::new(radius * phi.cos(), radius * phi.sin(), z)) as f64;
+            }
+        }
+        let integral = total * 4.0 * std::f64::consts::PI / (rows * columns) as f64;
+        assert!((integral - 1.0).abs() < 1e-2, "{}", integral);
+    }
+
+    #[test]
+    fn ConstantEnvironmentEstimateIsUnbiased() {
+        // A uniform sky checks the sinθ Jacobian: the mean of L / pdf is 4π L
+        let environment = EnvironmentLight::fromImage(Rgb32FImage::from_pixel(16, 8, image::Rgb([1.0, 1.0, 1.0])), 1.0, 0.0);
+        let mut rng = Rng::new(29, 31);
+        let count = 1 << 16;
+        let mut total = 0.0;
+        for _ in 0..count {
+            let sample = environment.sample(&Vector3::zeros(), &rng.next2D()).unwrap();
+            total += (sample.radiance.x / sample.pdf) as f64;
+        }
+        let estimate = total / count as f64;
+        assert!((estimate / (4.0 * std::f64::consts::PI) - 1.0).abs() < 1e-2, "{}", estimate);
+    }
+
+    #[test]
+    fn SphereLightSamplesMatchPdf() {
+        let mut world = World::new();
+        let emitter = Emitter(&mut world);
+        world.addSphere((0.5, 3.0, -0.2), 0.75, emitter);
+        world.commit();
+
+        CheckSampledPdfs(&world, &Vector3::zeros());
+        CheckSampledPdfs(&world, &Vector3::new(0.6, 2.0, 0.1));
+    }
+
+    #[test]
+    fn MeshLightSamplesMatchPdf() {
+        // Two triangles of different area facing down
+        let positions = vec![
+            Vector3::new(-1.0, 2.0, -1.0),
+            Vector3::new(1.0, 2.0, -1.0),
+            Vector3::new(1.0, 2.0, 1.0),
+            Vector3::new(-3.0, 2.0, 3.0),
+        ];
+        let mut world = World::new();
+        let emitter = Emitter(&mut world);
+        world.addTriangleMesh(TriangleMesh::new(positions, vec![(0, 1, 2), (0, 2, 3)]), emitter);
+        world.commit();
+
+        CheckSampledPdfs(&world, &Vector3::zeros());
+        CheckSampledPdfs(&world, &Vector3::new(2.0, 0.5, -1.0));
+    }
+}
@@ -0,0 +1,177 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use nalgebra::{Matrix3, Matrix4, Point3, Vector2, Vector3};
+use russimp::mesh::Mesh;
+use russimp::node::Node;
+use russimp::property::PropertyStore;
+use russimp::scene::{PostProcess, Scene};
+use russimp::{Matrix4x4, RussimpError};
+
+use crate::material::Material;
+use crate::texture::TextureCache;
+use crate::world::{TriangleMesh, World, DEFAULT_MATERIAL};
+
+pub struct LoadOptions {
+    pub postProcess: Vec<PostProcess>,  // steps russimp runs on the imported scene
+}
+
+impl Default for LoadOptions {
+    fn default() -> Self {
+        Self {
+            postProcess: vec![
+                PostProcess::Triangulate,
+                PostProcess::GenerateSmoothNormals,
+                // PostProcess::FlipUVs,
+                // PostProcess::FlipWindingOrder,
+                PostProcess::JoinIdenticalVertices,
+                PostProcess::OptimizeGraph,
+            ],
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    NotFound(PathBuf),
+    InvalidPath(PathBuf),
+    Import { path: PathBuf, error: RussimpError },
+    NonTriangularFace { path: PathBuf, mesh: String, vertices: usize },
+    IndexOutOfRange { path: PathBuf, mesh: String, index: u32 },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::NotFound(path) => write!(f, "model file '{}' does not exist", path.display()),
+            LoadError::InvalidPath(path) => write!(f, "model path '{}' is not valid UTF-8", path.display()),
+            LoadError::Import { path, error } => write!(f, "cannot import '{}': {}", path.display(), error),
+            LoadError::NonTriangularFace { path, mesh, vertices } => write!(
+                f, "mesh '{}' in '{}' has a face with {} vertices, enable PostProcess::Triangulate",
+                mesh, path.display(), vertices,
+            ),
+            LoadError::IndexOutOfRange { path, mesh, index } => write!(
+                f, "mesh '{}' in '{}' references missing vertex {}", mesh, path.display(), index,
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+// Validated triangle data of a russimp mesh in its local coordinate frame
+struct ImportedMesh {
+    positions: Vec<Point3<f32>>,
+    indices: Vec<(u32, u32, u32)>,
+    uvs: Option<Vec<Vector2<f32>>>,     // first texture coordinate channel
+    normals: Option<Vec<Vector3<f32>>>,
+    materialIndex: usize,   // index into the russimp scene materials
+}
+
+fn ImportMesh(path: &Path, mesh: &Mesh) -> Result<ImportedMesh, LoadError> {
+    let positions: Vec<Point3<f32>> = mesh.vertices.iter().map(|vertex| Point3::new(vertex.x, vertex.y, vertex.z)).collect();
+    let uvs = mesh.texture_coords.first()
+        .and_then(|channel| channel.as_ref())
+        .filter(|coordinates| coordinates.len() == positions.len())
+        .map(|coordinates| coordinates.iter().map(|uv| Vector2::new(uv.x, uv.y)).collect());
+    let normals = Some(&mesh.normals)
+        .filter(|normals| normals.len() == positions.len())
+        .map(|normals| normals.iter().map(|normal| Vector3::new(normal.x, normal.y, normal.z)).collect());
+
+    let mut indices: Vec<(u32, u32, u32)> = vec![];
+    for face in mesh.faces.iter() {
+        match face.0.len() {
+            // Points and lines have no surface to hit
+            0..=2 => continue,
+            3 => {}
+            count => return Err(LoadError::NonTriangularFace { path: path.to_path_buf(), mesh: mesh.name.clone(), vertices: count }),
+        }
+        if let Some(&index) = face.0.iter().find(|&&index| index as usize >= positions.len()) {
+            return Err(LoadError::IndexOutOfRange { path: path.to_path_buf(), mesh: mesh.name.clone(), index });
+        }
+        indices.push((face.0[0], face.0[1], face.0[2]));
+    }
+
+    Ok(ImportedMesh { positions, indices, uvs, normals, materialIndex: mesh.material_index as usize })
+}
+
+// russimp matrices are row major, a1..a4 being the first row
+fn ConvertMatrix(matrix: &Matrix4x4) -> Matrix4<f32> {
+    Matrix4::new(
+        matrix.a1, matrix.a2, matrix.a3, matrix.a4,
+        matrix.b1, matrix.b2, matrix.b3, matrix.b4,
+        matrix.c1, matrix.c2, matrix.c3, matrix.c4,
+        matrix.d1, matrix.d2, matrix.d3, matrix.d4,
+    )
+}
+
+// Walks the node graph depth first, collecting every (mesh index, node to model transform) pair
+fn CollectMeshInstances(node: &Node, parentTransform: &Matrix4<f32>, instances: &mut Vec<(usize, Matrix4<f32>)>) {
+    let transform = parentTransform * ConvertMatrix(&node.transformation);
+    for &meshIndex in node.meshes.iter() {
+        instances.push((meshIndex as usize, transform));
+    }
+    for child in node.children.borrow().iter() {
+        CollectMeshInstances(child, &transform, instances);
+    }
+}
+
+// Adds all meshes of a model file to the world, placed by the node hierarchy and then by transform.
+// Meshes use materialOverride when given, their own russimp material otherwise. The caller commits the world once everything is added.
+//
+// rust-embree does not expose instanced geometry, so a mesh referenced by several nodes is added
+// once per node with its vertices baked into world space.
+pub fn LoadModel(world: &mut World, path: &Path, transform: &Matrix4<f32>, materialOverride: Option<usize>, options: &LoadOptions) -> Result<(), LoadError> {
+    if !path.is_file() {
+        return Err(LoadError::NotFound(path.to_path_buf()));
+    }
+    let pathString = path.to_str().ok_or_else(|| LoadError::InvalidPath(path.to_path_buf()))?;
+
+    let props = PropertyStore;
+    let scene = Scene::from_file_with_props(pathString, options.postProcess.clone(), &props)
+        .map_err(|error| LoadError::Import { path: path.to_path_buf(), error })?;
+
+    let meshes = scene.meshes.iter()
+        .map(|mesh| ImportMesh(path, mesh))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Register the model materials with the world, unless everything uses the override
+    let modelDirectory = path.parent().unwrap_or(Path::new(""));
+    let mut textures = TextureCache::default();
+    let materials: Vec<usize> = match materialOverride {
+        Some(_) => vec![],
+        None => scene.materials.iter()
+            .map(|material| world.addMaterial(Material::fromRussimp(material, modelDirectory, &mut textures)))
+            .collect(),
+    };
+
+    let mut instances = vec![];
+    match &scene.root {
+        Some(root) => CollectMeshInstances(root, transform, &mut instances),
+        None => instances.extend((0..meshes.len()).map(|meshIndex| (meshIndex, *transform))),
+    }
+
+    for (meshIndex, meshTransform) in instances {
+        let mesh = match meshes.get(meshIndex) {
+            Some(mesh) if !mesh.indices.is_empty() => mesh,
+            _ => continue,
+        };
+
+        let positions = mesh.positions.iter().map(|position| meshTransform.transform_point(position).coords).collect();
+        let mut triangleMesh = TriangleMesh::new(positions, mesh.indices.clone());
+        triangleMesh.uvs = mesh.uvs.clone();
+
+        // Normals transform with the inverse transpose to stay perpendicular under non-uniform scale
+        let linear: Matrix3<f32> = meshTransform.fixed_view::<3, 3>(0, 0).into();
+        let normalMatrix = linear.try_inverse().unwrap_or_else(Matrix3::identity).transpose();
+        triangleMesh.normals = mesh.normals.as_ref()
+            .map(|normals| normals.iter().map(|normal| normalMatrix * normal).collect());
+
+        let material = materialOverride
+            .or_else(|| materials.get(mesh.materialIndex).copied())
+            .unwrap_or(DEFAULT_MATERIAL);
+        world.addTriangleMesh(triangleMesh, material);
+    }
+
+    Ok(())
+}
@@ -1,47 +1,73 @@
 #![allow(non_snake_case)]
 
-use std::cell::RefCell;
-use std::rc::Rc;
-use raylib::color::Color as RaylibColor;
-use eframe::{egui, App, NativeOptions};
-use eframe::egui::{Image, Rect, Ui};
-use eframe::egui::CentralPanel;
-use eframe::egui::ImageData::{Color as EguiColor, Color};
-use nalgebra::{Matrix4, Rotation3, Vector3};
-use raylib::ffi::{DrawCube, GenMeshCylinder};
+use std::process::ExitCode;
+use eframe::NativeOptions;
+use nalgebra::Vector3;
 
 
 mod vec_ops;
 
+mod sampling;
+mod sampler;
+mod bsdf;
+mod intersection;
+mod light;
+mod integrator;
+mod aov;
+
+mod film;
+mod accumulator;
+mod tonemap;
+mod export;
+mod material;
+mod texture;
+mod world;
+mod loader;
+mod scene_file;
+mod tiles;
+
+mod distortion;
+mod projection;
 mod camera;
-use crate::camera::Camera;
+mod calibration;
 
 mod renderer;
 use crate::renderer::Renderer;
 
-impl App for Renderer {
-    fn update(&mut self, ctx: &egui::Context, _: &mut eframe::Frame) {
-        CentralPanel::default().show(ctx, |ui: &mut Ui| {
-            let size = ui.min_rect().max;
+mod render_thread;
+
+mod viewer;
+use crate::viewer::Viewer;
+
+mod cli;
+use crate::cli::{ParseRenderArgs, RenderHeadless, USAGE};
 
-            // Render every frame
-            self.camera.resize(size.x, size.y);
-            self.renderNormalsToTexture(Some(ctx));
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().collect();
 
-            ui.label("Hello bro");
+    // Headless mode: rust-rendering render --scene <file> --out <image> ...
+    if args.len() > 1 {
+        if args[1] != "render" {
+            eprintln!("{}", USAGE);
+            return ExitCode::FAILURE;
+        }
 
-            if let Some(ref texture) = self.renderTexture {
-                let img = Image::from_texture(texture);
-                img.paint_at(ui, Rect {
-                    min: egui::pos2(ui.min_rect().min.x, ui.min_rect().min.y),
-                    max: egui::pos2(self.camera.imageWidth, self.camera.imageHeight),
-                });
+        let renderArgs = match ParseRenderArgs(&args[2..]) {
+            Ok(renderArgs) => renderArgs,
+            Err(error) => {
+                eprintln!("error: {}\n\n{}", error, USAGE);
+                return ExitCode::FAILURE;
             }
-        });
-    }
-}
+        };
 
-fn main() {
+        return match RenderHeadless(&renderArgs) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(error) => {
+                eprintln!("error: {}", error);
+                ExitCode::FAILURE
+            }
+        };
+    }
 
     let mut renderer = Renderer::new();
     renderer.camera.setRotation(&Vector3::y_axis(), 180.0_f32.to_radians());
@@ -49,7 +75,7 @@ fn main() {
 
     renderer.createDemoScene();
 
-    // renderer.loadScene();
+    // renderer.loadScene("/home/mujin/workdesk/Sponza/sponza.obj", &LoadOptions::default()).unwrap();
 
     // let (mut rl, thread) = raylib::init().size(800, 600).title("Hello").build();
     //
@@ -105,6 +131,8 @@ fn main() {
     eframe::run_native(
         "",
         options,
-        Box::new(|cc| Ok(Box::new(renderer))),
+        Box::new(|cc| Ok(Box::new(Viewer::new(renderer, &cc.egui_ctx)))),
     ).unwrap();
+
+    ExitCode::SUCCESS
 }
\ No newline at end of file
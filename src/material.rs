@@ -0,0 +1,240 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use nalgebra::{Vector3, Vector4};
+use russimp::material::{Material as RussimpMaterial, PropertyTypeInfo, TextureType};
+
+use crate::bsdf::{Bsdf, Conductor, Dielectric, GgxDistribution, Lambertian, Plastic};
+use crate::intersection::SurfaceHit;
+use crate::texture::{ColorSpace, Texture, TextureCache};
+
+#[derive(Clone, Debug)]
+pub struct Material {
+    pub name: String,
+    pub diffuse: Vector3<f32>,
+    pub diffuseTexture: Option<Arc<Texture>>,     // replaces diffuse, its alpha scales opacity
+    pub normalTexture: Option<Arc<Texture>>,      // tangent space normal map
+    pub specular: Vector3<f32>,
+    pub roughness: f32,
+    pub roughnessTexture: Option<Arc<Texture>>,
+    pub roughnessChannel: usize,                  // 1 (green) for combined glTF metallic-roughness maps
+    pub metalness: f32,
+    pub emission: Vector3<f32>,
+    pub opacity: f32,
+    pub opacityTexture: Option<Arc<Texture>>,
+    pub ior: f32,
+    pub transmission: f32,                        // above 0.5 the surface is a glass-like dielectric
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            name: String::from("default"),
+            diffuse: Vector3::new(0.8, 0.8, 0.8),
+            diffuseTexture: None,
+            normalTexture: None,
+            specular: Vector3::zeros(),
+            roughness: 1.0,
+            roughnessTexture: None,
+            roughnessChannel: 0,
+            metalness: 0.0,
+            emission: Vector3::zeros(),
+            opacity: 1.0,
+            opacityTexture: None,
+            ior: 1.5,
+            transmission: 0.0,
+        }
+    }
+}
+
+fn FloatProperty(values: &[f32]) -> Option<f32> {
+    values.first().copied()
+}
+
+fn ColorProperty(values: &[f32]) -> Option<Vector3<f32>> {
+    match values {
+        [r, g, b, ..] => Some(Vector3::new(*r, *g, *b)),
+        _ => None,
+    }
+}
+
+// Phong exponent to a GGX roughness with a comparable highlight width
+fn ShininessToRoughness(shininess: f32) -> f32 {
+    (2.0 / (shininess.max(0.0) + 2.0)).sqrt()
+}
+
+impl Material {
+    pub fn isEmissive(&self) -> bool {
+        self.emission.max() > 0.0
+    }
+
+    // Whether opacityAt may be below 1, letting rays through parts of the surface
+    pub fn isCutOut(&self) -> bool {
+        self.opacity < 1.0 || self.opacityTexture.is_some() || self.diffuseTexture.as_ref().is_some_and(|texture| !texture.isOpaque())
+    }
+
+    // Texture lookups take the log2 uv footprint of the shading point, see SurfaceHit::uvFootprint
+    fn sampleTexture(texture: &Texture, hit: &SurfaceHit, uvFootprint: f32) -> Vector4<f32> {
+        texture.sample(&hit.uv, uvFootprint + texture.lodBias())
+    }
+
+    pub fn diffuseAt(&self, hit: &SurfaceHit, uvFootprint: f32) -> Vector3<f32> {
+        match &self.diffuseTexture {
+            Some(texture) => Self::sampleTexture(texture, hit, uvFootprint).xyz(),
+            None => self.diffuse,
+        }
+    }
+
+    pub fn roughnessAt(&self, hit: &SurfaceHit, uvFootprint: f32) -> f32 {
+        match &self.roughnessTexture {
+            Some(texture) => Self::sampleTexture(texture, hit, uvFootprint)[self.roughnessChannel].clamp(0.0, 1.0),
+            None => self.roughness,
+        }
+    }
+
+    pub fn opacityAt(&self, hit: &SurfaceHit, uvFootprint: f32) -> f32 {
+        let mut opacity = self.opacity;
+        if let Some(texture) = &self.diffuseTexture {
+            opacity *= Self::sampleTexture(texture, hit, uvFootprint).w;
+        }
+        if let Some(texture) = &self.opacityTexture {
+            opacity *= Self::sampleTexture(texture, hit, uvFootprint).x;
+        }
+        opacity
+    }
+
+    // Applies the normal map around the given normal, which must face the same side as the
+    // geometric normal of the hit
+    pub fn perturbNormal(&self, hit: &SurfaceHit, normal: &Vector3<f32>, uvFootprint: f32) -> Vector3<f32> {
+        let (texture, tangent) = match (&self.normalTexture, hit.tangent) {
+            (Some(texture), Some(tangent)) => (texture, tangent),
+            _ => return *normal,
+        };
+
+        let tangent = match (tangent - normal * normal.dot(&tangent)).try_normalize(1e-6) {
+            Some(tangent) => tangent,
+            None => return *normal,
+        };
+        let bitangent = normal.cross(&tangent);
+
+        let texel = Self::sampleTexture(texture, hit, uvFootprint);
+        let local = texel.xyz() * 2.0 - Vector3::new(1.0, 1.0, 1.0);
+        let perturbed = (tangent * local.x + bitangent * local.y + normal * local.z.max(1e-3)).normalize();
+
+        // Keep the shading normal on the visible side of the surface
+        if perturbed.dot(&hit.geometricNormal) <= 0.0 { *normal } else { perturbed }
+    }
+
+    // Scattering model at the hit: glass when transmissive, metal when metallic, a coated diffuse
+    // surface when it has a specular color and plain diffuse otherwise
+    pub fn bsdfAt(&self, hit: &SurfaceHit, uvFootprint: f32) -> Box<dyn Bsdf> {
+        let distribution = GgxDistribution::fromRoughness(self.roughnessAt(hit, uvFootprint));
+
+        if self.transmission > 0.5 {
+            // The local frame faces the incoming ray, so rays from inside see the inverse ratio
+            let eta = if hit.frontFacing { self.ior } else { 1.0 / self.ior };
+            return Box::new(Dielectric { eta, distribution });
+        }
+
+        let color = self.diffuseAt(hit, uvFootprint);
+        if self.metalness > 0.5 {
+            return Box::new(Conductor::fromReflectance(&color, self.roughnessAt(hit, uvFootprint)));
+        }
+        if self.specular.max() > 0.0 {
+            return Box::new(Plastic { diffuse: color, eta: self.ior, distribution });
+        }
+        Box::new(Lambertian { albedo: color })
+    }
+
+    // Reads the standard assimp material keys. Texture paths are resolved against the directory
+    // of the model file and loaded through the cache.
+    pub fn fromRussimp(source: &RussimpMaterial, modelDirectory: &Path, textures: &mut TextureCache) -> Self {
+        let mut material = Material::default();
+        let mut baseColor = None;
+        let mut emissiveIntensity = 1.0;
+        let mut metalnessFile = None;
+        let mut roughnessFile = None;
+
+        for property in source.properties.iter() {
+            match (property.key.as_str(), &property.data) {
+                ("?mat.name", PropertyTypeInfo::String(name)) => material.name = name.clone(),
+                ("$clr.diffuse", PropertyTypeInfo::FloatArray(values)) => {
+                    material.diffuse = ColorProperty(values).unwrap_or(material.diffuse);
+                }
+                ("$clr.base", PropertyTypeInfo::FloatArray(values)) => baseColor = ColorProperty(values),
+                ("$clr.specular", PropertyTypeInfo::FloatArray(values)) => {
+                    material.specular = ColorProperty(values).unwrap_or(material.specular);
+                }
+                ("$clr.emissive", PropertyTypeInfo::FloatArray(values)) => {
+                    material.emission = ColorProperty(values).unwrap_or(material.emission);
+                }
+                ("$mat.emissiveIntensity", PropertyTypeInfo::FloatArray(values)) => {
+                    emissiveIntensity = FloatProperty(values).unwrap_or(1.0);
+                }
+                ("$mat.opacity", PropertyTypeInfo::FloatArray(values)) => {
+                    material.opacity = FloatProperty(values).unwrap_or(1.0);
+                }
+                ("$mat.refracti", PropertyTypeInfo::FloatArray(values)) => {
+                    material.ior = FloatProperty(values).filter(|ior| *ior >= 1.0).unwrap_or(material.ior);
+                }
+                ("$mat.shininess", PropertyTypeInfo::FloatArray(values)) => {
+                    if let Some(shininess) = FloatProperty(values) {
+                        material.roughness = ShininessToRoughness(shininess);
+                    }
+                }
+                ("$mat.roughnessFactor", PropertyTypeInfo::FloatArray(values)) => {
+                    material.roughness = FloatProperty(values).unwrap_or(material.roughness);
+                }
+                ("$mat.metallicFactor", PropertyTypeInfo::FloatArray(values)) => {
+                    material.metalness = FloatProperty(values).unwrap_or(material.metalness);
+                }
+                ("$mat.transmission.factor", PropertyTypeInfo::FloatArray(values)) => {
+                    material.transmission = FloatProperty(values).unwrap_or(material.transmission);
+                }
+                ("$tex.file", PropertyTypeInfo::String(file)) if property.index == 0 => {
+                    // Embedded textures are referenced as "*<index>" and are not supported
+                    if file.starts_with('*') {
+                        eprintln!("warning: embedded texture '{}' of material '{}' is ignored", file, material.name);
+                        continue;
+                    }
+
+                    let path = modelDirectory.join(file.replace('\\', "/"));
+                    match property.semantic {
+                        TextureType::Diffuse | TextureType::BaseColor => {
+                            material.diffuseTexture = textures.load(&path, ColorSpace::Srgb);
+                        }
+                        TextureType::Normals | TextureType::NormalCamera => {
+                            material.normalTexture = textures.load(&path, ColorSpace::Linear);
+                        }
+                        TextureType::Opacity => {
+                            material.opacityTexture = textures.load(&path, ColorSpace::Linear);
+                        }
+                        TextureType::Roughness => roughnessFile = Some(path),
+                        TextureType::Metalness => metalnessFile = Some(path),
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(path) = roughnessFile {
+            material.roughnessChannel = if metalnessFile.as_ref() == Some(&path) { 1 } else { 0 };
+            material.roughnessTexture = textures.load(&path, ColorSpace::Linear);
+        }
+
+        // PBR base color wins over the legacy diffuse color when both are present
+        if let Some(baseColor) = baseColor {
+            material.diffuse = baseColor;
+        }
+
+        material.diffuse = material.diffuse.map(|value| value.clamp(0.0, 1.0));
+        material.specular = material.specular.map(|value| value.clamp(0.0, 1.0));
+        material.emission = (material.emission * emissiveIntensity).map(|value| value.max(0.0));
+        material.roughness = material.roughness.clamp(0.0, 1.0);
+        material.metalness = material.metalness.clamp(0.0, 1.0);
+        material.opacity = material.opacity.clamp(0.0, 1.0);
+        material.transmission = material.transmission.clamp(0.0, 1.0);
+        material
+    }
+}
@@ -0,0 +1,126 @@
+use nalgebra::{Vector2, Vector3};
+
+// How camera rays leave the camera. All projections look down the camera +z axis with +y up.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    Perspective,                        // pinhole through the intrinsic camera matrix
+    Orthographic { height: f32 },       // parallel rays, height is the extent of the image in scene units
+    FisheyeEquidistant { fov: f32 },    // angle from the axis grows linearly with the image radius
+    FisheyeEquisolid { fov: f32 },      // equal solid angle per pixel area
+    Equirectangular,                    // full sphere laid out like environment maps, see EquirectDirection
+    Cubemap,                            // six 90 degree faces in a 3 x 2 layout: +x -x +y / -y +z -z
+}
+
+impl Projection {
+    pub const ALL: [Projection; 6] = [
+        Projection::Perspective,
+        Projection::Orthographic { height: 4.0 },
+        Projection::FisheyeEquidistant { fov: 180.0 },
+        Projection::FisheyeEquisolid { fov: 180.0 },
+        Projection::Equirectangular,
+        Projection::Cubemap,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Projection::Perspective => "Perspective",
+            Projection::Orthographic { .. } => "Orthographic",
+            Projection::FisheyeEquidistant { .. } => "Fisheye (equidistant)",
+            Projection::FisheyeEquisolid { .. } => "Fisheye (equisolid)",
+            Projection::Equirectangular => "Equirectangular",
+            Projection::Cubemap => "Cubemap",
+        }
+    }
+
+    // Command line spelling
+    pub fn key(&self) -> &'static str {
+        match self {
+            Projection::Perspective => "perspective",
+            Projection::Orthographic { .. } => "orthographic",
+            Projection::FisheyeEquidistant { .. } => "fisheye-equidistant",
+            Projection::FisheyeEquisolid { .. } => "fisheye-equisolid",
+            Projection::Equirectangular => "equirectangular",
+            Projection::Cubemap => "cubemap",
+        }
+    }
+
+    // Projection with default parameters, a 4 unit orthographic view and 180 degree fisheyes
+    pub fn fromKey(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|projection| projection.key() == key)
+    }
+
+    // Only projections with a focus plane in front of the camera have depth of field
+    pub fn hasLens(&self) -> bool {
+        matches!(self, Projection::Perspective | Projection::Orthographic { .. })
+    }
+}
+
+// Direction of a circular fisheye for an offset from the image center, None outside the image
+// circle of the given radius. fov is in degrees across the whole circle.
+pub fn FisheyeDirection(offset: &Vector2<f32>, circleRadius: f32, fov: f32, equisolid: bool) -> Option<Vector3<f32>> {
+    let radius = offset.norm() / circleRadius;
+    if radius > 1.0 {
+        return None;
+    }
+
+    let halfFov = fov.to_radians() / 2.0;
+    let theta = if equisolid {
+        2.0 * (radius * (halfFov / 2.0).sin()).clamp(-1.0, 1.0).asin()
+    } else {
+        radius * halfFov
+    };
+
+    let azimuth = if radius > 0.0 { offset / offset.norm() } else { Vector2::zeros() };
+    Some(Vector3::new(theta.sin() * azimuth.x, theta.sin() * azimuth.y, theta.cos()))
+}
+
+// Offset from the image center of a unit direction, the inverse of FisheyeDirection. Directions
+// beyond the field of view land outside the image circle.
+pub fn FisheyeOffset(direction: &Vector3<f32>, circleRadius: f32, fov: f32, equisolid: bool) -> Vector2<f32> {
+    let halfFov = fov.to_radians() / 2.0;
+    let theta = direction.z.clamp(-1.0, 1.0).acos();
+    let radius = if equisolid { (theta / 2.0).sin() / (halfFov / 2.0).sin() } else { theta / halfFov };
+
+    let sideways = direction.xy().norm();
+    let azimuth = if sideways > 0.0 { direction.xy() / sideways } else { Vector2::zeros() };
+    azimuth * (radius * circleRadius)
+}
+
+// Image left and image up axes of a cube face, followed by its viewing direction
+type CubeFace = (Vector3<f32>, Vector3<f32>, Vector3<f32>);
+
+// Faces in their rows and columns of the final image
+fn CubeFaces() -> [[CubeFace; 3]; 2] {
+    let (x, y, z) = (Vector3::x(), Vector3::y(), Vector3::z());
+    [
+        [(-z, y, x), (z, y, -x), (x, -z, y)],
+        [(x, z, -y), (x, y, z), (-x, y, -z)],
+    ]
+}
+
+// Direction of a position in [0, 1]^2 of the final image split into cube faces. Every face is a
+// 90 degree perspective view of a camera turned towards its axis, oriented like the main view.
+pub fn CubemapDirection(u: f32, v: f32) -> Vector3<f32> {
+    let column = ((u * 3.0) as usize).min(2);
+    let row = ((v * 2.0) as usize).min(1);
+    let s = u * 3.0 - column as f32;
+    let t = v * 2.0 - row as f32;
+
+    let (left, up, forward) = CubeFaces()[row][column];
+    (forward + left * (1.0 - 2.0 * s) + up * (1.0 - 2.0 * t)).normalize()
+}
+
+// Position in [0, 1]^2 of the final image of a direction, the inverse of CubemapDirection. The
+// face is the one whose axis is closest to the direction.
+pub fn CubemapPosition(direction: &Vector3<f32>) -> Vector2<f32> {
+    let faces = CubeFaces();
+    let (row, column) = (0..2).flat_map(|row| (0..3).map(move |column| (row, column)))
+        .max_by(|&(r0, c0), &(r1, c1)| direction.dot(&faces[r0][c0].2).total_cmp(&direction.dot(&faces[r1][c1].2)))
+        .unwrap();
+
+    let (left, up, forward) = faces[row][column];
+    let onFace = direction / direction.dot(&forward);
+    let s = (1.0 - onFace.dot(&left)) / 2.0;
+    let t = (1.0 - onFace.dot(&up)) / 2.0;
+    Vector2::new((column as f32 + s) / 3.0, (row as f32 + t) / 2.0)
+}
@@ -0,0 +1,180 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use eframe::egui;
+use image::Rgb32FImage;
+use nalgebra::Vector3;
+
+use crate::camera::Camera;
+use crate::export::SaveFloatImage;
+use crate::film::Filter;
+use crate::integrator::IntegratorKind;
+use crate::renderer::Renderer;
+use crate::sampler::SamplerKind;
+use crate::tiles::Tile;
+
+pub enum RenderCommand {
+    SetCamera(Camera),
+    SetIntegrator(IntegratorKind),
+    SetSampler(SamplerKind),
+    SetFilter(Filter),
+    // Focuses the camera on the surface at the image center
+    Autofocus,
+    // Writes the accumulated image and the AOVs, see SaveFloatImage
+    Save(PathBuf),
+    Shutdown,
+}
+
+pub enum RenderUpdate {
+    // Finished tile of the first pass after a change, for quick feedback
+    Tile { generation: u64, tile: Tile, radiances: Vec<Vector3<f32>> },
+    // Running average of all passes accumulated so far
+    Pass { generation: u64, passes: u32, image: Rgb32FImage },
+    Saved { path: PathBuf, result: Result<(), String> },
+    // Focus distance found by autofocus, None when nothing is at the image center
+    Focused { generation: u64, distance: Option<f32> },
+}
+
+// Owns the renderer on a worker thread and streams results back to the UI
+pub struct RenderThread {
+    commands: Sender<(u64, RenderCommand)>,
+    updates: Receiver<RenderUpdate>,
+    generation: Arc<AtomicU64>,     // bumped on every command, in-flight passes of older generations are cancelled
+    handle: Option<JoinHandle<()>>,
+}
+
+impl RenderThread {
+    pub fn spawn(renderer: Renderer, ctx: egui::Context) -> Self {
+        let (commands, commandReceiver) = channel();
+        let (updateSender, updates) = channel();
+        let generation = Arc::new(AtomicU64::new(0));
+
+        let workerGeneration = generation.clone();
+        let handle = thread::spawn(move || {
+            RenderLoop(renderer, commandReceiver, updateSender, workerGeneration, ctx);
+        });
+
+        Self {
+            commands,
+            updates,
+            generation,
+            handle: Some(handle),
+        }
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    pub fn setCamera(&self, camera: Camera) {
+        self.send(RenderCommand::SetCamera(camera));
+    }
+
+    pub fn setIntegrator(&self, kind: IntegratorKind) {
+        self.send(RenderCommand::SetIntegrator(kind));
+    }
+
+    pub fn autofocus(&self) {
+        self.send(RenderCommand::Autofocus);
+    }
+
+    // Unlike the other commands this keeps the image, the pass in flight finishes before saving
+    pub fn save(&self, path: PathBuf) {
+        let _ = self.commands.send((self.generation(), RenderCommand::Save(path)));
+    }
+
+    pub fn setSampler(&self, kind: SamplerKind) {
+        self.send(RenderCommand::SetSampler(kind));
+    }
+
+    pub fn setFilter(&self, filter: Filter) {
+        self.send(RenderCommand::SetFilter(filter));
+    }
+
+    pub fn tryRecv(&self) -> Option<RenderUpdate> {
+        self.updates.try_recv().ok()
+    }
+
+    fn send(&self, command: RenderCommand) {
+        let generation = self.generation.fetch_add(1, Ordering::AcqRel) + 1;
+        let _ = self.commands.send((generation, command));
+    }
+}
+
+impl Drop for RenderThread {
+    fn drop(&mut self) {
+        self.send(RenderCommand::Shutdown);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn RenderLoop(
+    mut renderer: Renderer,
+    commands: Receiver<(u64, RenderCommand)>,
+    updates: Sender<RenderUpdate>,
+    sharedGeneration: Arc<AtomicU64>,
+    ctx: egui::Context,
+) {
+    // Generation of the last applied command, results are tagged with it
+    let mut generation = 0;
+
+    loop {
+        // Apply pending commands, blocking while there is nothing left to refine
+        loop {
+            let (commandGeneration, command) = if renderer.isConverged() {
+                match commands.recv() {
+                    Ok(command) => command,
+                    Err(_) => return,
+                }
+            } else {
+                match commands.try_recv() {
+                    Ok(command) => command,
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return,
+                }
+            };
+
+            generation = commandGeneration;
+            match command {
+                RenderCommand::SetCamera(camera) => renderer.setCamera(camera),
+                RenderCommand::SetIntegrator(kind) => renderer.setIntegrator(kind),
+                RenderCommand::SetSampler(kind) => renderer.setSampler(kind),
+                RenderCommand::SetFilter(filter) => renderer.setFilter(filter),
+                RenderCommand::Autofocus => {
+                    let distance = renderer.autofocus();
+                    let _ = updates.send(RenderUpdate::Focused { generation, distance });
+                    ctx.request_repaint();
+                }
+                RenderCommand::Save(path) => {
+                    let image = renderer.accumulatedImage();
+                    let aovs = renderer.renderAovs();
+                    let result = SaveFloatImage(&path, &image, Some(&aovs)).map_err(|error| error.to_string());
+                    let _ = updates.send(RenderUpdate::Saved { path, result });
+                    ctx.request_repaint();
+                }
+                RenderCommand::Shutdown => return,
+            }
+        }
+
+        // Also cancels right away when a command is still on its way, it is picked up next iteration
+        let isCancelled = || sharedGeneration.load(Ordering::Acquire) != generation;
+
+        let average = renderer.accumulatePass(isCancelled, |tile, radiances| {
+            let _ = updates.send(RenderUpdate::Tile { generation, tile: *tile, radiances: radiances.to_vec() });
+            ctx.request_repaint();
+        });
+
+        if let Some(image) = average {
+            let passes = renderer.accumulatedPasses();
+            if updates.send(RenderUpdate::Pass { generation, passes, image }).is_err() {
+                return;
+            }
+            ctx.request_repaint();
+        }
+    }
+}
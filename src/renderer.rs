@@ -1,17 +1,20 @@
-use std::cell::RefCell;
-use std::rc::Rc;
-use eframe::egui;
-use eframe::egui::{Color32, ColorImage, TextureHandle};
-use image::{ImageBuffer, Rgb};
-use nalgebra::{Matrix4, Rotation3, Vector3};
-use rust_embree::{CastRay, CommitScene, CreateDevice, CreateScene, CreateSphereGeometry, CreateTriangleGeometry, EmbreeDevice, EmbreeScene};
+use std::path::Path;
+use eframe::egui::{Color32, ColorImage};
+use image::{ImageBuffer, ImageError, Rgb, Rgb32FImage};
+use nalgebra::{Matrix4, Vector2, Vector3};
 
+use crate::accumulator::Accumulator;
+use crate::aov::{AovBuffers, AovSample, ShadeAov};
 use crate::camera::Camera;
-
-use russimp::node::Node;
-use russimp::property::Property;
-use russimp::scene::PostProcess;
-use russimp::{property::PropertyStore, scene::Scene};
+use crate::film::{Film, Filter};
+use crate::integrator::{Integrator, IntegratorKind};
+use crate::light::EnvironmentLight;
+use crate::loader::{LoadError, LoadModel, LoadOptions};
+use crate::sampler::SamplerKind;
+use crate::scene_file::{ParseSceneFile, SceneFileError};
+use crate::tonemap::DisplaySettings;
+use crate::tiles::{DefaultThreadCount, GenerateTiles, RenderTilesParallel, Tile, TILE_SIZE};
+use crate::world::{TriangleMesh, World, DEFAULT_MATERIAL};
 
 
 pub fn CreateEguiColorImageFromImageBuffer(imageBuffer: &ImageBuffer<Rgb<u8>, Vec<u8>>) -> ColorImage {
@@ -26,131 +29,319 @@ pub fn CreateEguiColorImageFromImageBuffer(imageBuffer: &ImageBuffer<Rgb<u8>, Ve
     }
 }
 
-fn LoadEguiTextureFromImageBuffer(ctx: &egui::Context, imageBuffer: &ImageBuffer<Rgb<u8>, Vec<u8>>) -> TextureHandle {
-    let eguiColorImage = CreateEguiColorImageFromImageBuffer(imageBuffer);
-    ctx.load_texture("", eguiColorImage, Default::default())
+// Tone maps and encodes the linear framebuffer for display or 8 bit export
+pub fn QuantizeImageBuffer(hdrImageBuffer: &Rgb32FImage, settings: &DisplaySettings) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+    settings.encodeImage(hdrImageBuffer)
 }
 
 pub struct Renderer {
-    pub renderTexture: Option<TextureHandle>,
     pub camera: Camera,
-    device: EmbreeDevice,
-    scene: EmbreeScene,
+    pub integratorKind: IntegratorKind,
+    integrator: Box<dyn Integrator>,
+    pub samplerKind: SamplerKind,
+    pub filter: Filter,
+    pub maxAccumulatedPasses: u32,
+    pub threadCount: usize,
+    accumulator: Accumulator,
+    pub world: World,
 }
 
 impl Renderer {
     pub fn new() -> Self {
-        let device = CreateDevice();
-        let scene = CreateScene(&device);
-
         Self {
-            renderTexture: None,
             camera: Camera::new(Matrix4::<f32>::identity(), 45.0, 640.0, 480.0),
-            device,
-            scene,
+            integratorKind: IntegratorKind::Normals,
+            integrator: IntegratorKind::Normals.create(),
+            samplerKind: SamplerKind::Sobol,
+            filter: Filter::Box,
+            maxAccumulatedPasses: 1024,
+            threadCount: DefaultThreadCount(),
+            accumulator: Accumulator::new(),
+            world: World::new(),
         }
     }
 
     pub fn createDemoScene(&mut self) {
         // Quad vertices and indices
-        let vertices: &[(f32, f32, f32)] = &[
-            (-1.5, -1.5, 0.0),
-            (1.5, -1.5, 0.0),
-            (1.5, 1.5, 0.0),
-            (-1.5, 1.5, 0.0)
+        let vertices = vec![
+            Vector3::new(-1.5, -1.5, 0.0),
+            Vector3::new(1.5, -1.5, 0.0),
+            Vector3::new(1.5, 1.5, 0.0),
+            Vector3::new(-1.5, 1.5, 0.0)
         ];
 
-        let indices: &[(u32, u32, u32)] = &[
+        let indices = vec![
             (0, 1, 2),
             (0, 2, 3),
         ];
 
-        CreateTriangleGeometry(
-            &self.device,
-            &self.scene,
-            vertices,
-            indices,
+        let mut quad = TriangleMesh::new(vertices, indices);
+        quad.uvs = Some(vec![
+            Vector2::new(0.0, 0.0),
+            Vector2::new(1.0, 0.0),
+            Vector2::new(1.0, 1.0),
+            Vector2::new(0.0, 1.0),
+        ]);
+
+        self.world.addTriangleMesh(
+            quad,
+            DEFAULT_MATERIAL,
         );
 
-        CreateSphereGeometry(
-            &self.device,
-            &self.scene,
+        self.world.addSphere(
             (0.0, 0.0, 0.0),
             1.0,
+            DEFAULT_MATERIAL,
         );
 
-        CommitScene(&self.scene);
-    }
-
-    pub fn loadScene(&mut self) {
-        // load sponza
-        let props: PropertyStore = PropertyStore::default();
-
-        let scene = Scene::from_file_with_props(
-            "/home/mujin/workdesk/Sponza/sponza.obj",
-            vec![
-                PostProcess::Triangulate,
-                PostProcess::GenerateSmoothNormals,
-                // PostProcess::FlipUVs,
-                // PostProcess::FlipWindingOrder,
-                PostProcess::JoinIdenticalVertices,
-                PostProcess::OptimizeGraph,
-            ],
-            &props,
-        )
-            .unwrap();
-
-        for mesh in scene.meshes {
-            let mut vertices: Vec<(f32, f32, f32)> = vec![];
-            let mut indices: Vec<(u32, u32, u32)> = vec![];
-            for vertex in mesh.vertices.iter() {
-                vertices.push((vertex.x, vertex.y, vertex.z));
-            }
-            for face in mesh.faces {
-                indices.push((face.0[0], face.0[1], face.0[2]));
-            }
+        self.world.commit();
+        self.accumulator.reset();
+    }
 
-            CreateTriangleGeometry(&self.device, &self.scene, &vertices, &indices);
-            CommitScene(&self.scene);
-        }
+    // Loads a model file with russimp and adds its meshes to the world, committing once at the end
+    pub fn loadScene(&mut self, path: impl AsRef<Path>, options: &LoadOptions) -> Result<(), LoadError> {
+        LoadModel(&mut self.world, path.as_ref(), &Matrix4::identity(), None, options)?;
+        self.commitWorld();
+        Ok(())
+    }
+
+    pub fn loadSceneFile(&mut self, path: &Path) -> Result<(), SceneFileError> {
+        let description = ParseSceneFile(path)?;
+        description.build(self, path.parent().unwrap_or(Path::new("")))
+    }
+
+    // Equirectangular .hdr or .exr image lighting the scene from all directions
+    pub fn loadEnvironment(&mut self, path: &Path, intensity: f32, rotationDegrees: f32) -> Result<(), ImageError> {
+        self.world.setEnvironment(EnvironmentLight::load(path, intensity, rotationDegrees)?);
+        self.accumulator.reset();
+        Ok(())
+    }
+
+    // Call after adding geometry to the world directly
+    pub fn commitWorld(&mut self) {
+        self.world.commit();
+        self.accumulator.reset();
+    }
+
+    // Replaces the camera and drops the passes rendered with the old one; edits made through the
+    // camera field directly go unnoticed by the accumulator
+    pub fn setCamera(&mut self, camera: Camera) {
+        self.camera = camera;
+        self.accumulator.reset();
     }
 
+    // Focuses on the surface at the image center, see Camera::autofocus
+    pub fn autofocus(&mut self) -> Option<f32> {
+        let distance = self.camera.autofocus(&self.world)?;
+        self.accumulator.reset();
+        Some(distance)
+    }
+
+    pub fn setSampler(&mut self, kind: SamplerKind) {
+        self.samplerKind = kind;
+        self.accumulator.reset();
+    }
+
+    pub fn setFilter(&mut self, filter: Filter) {
+        self.filter = filter;
+        self.accumulator.reset();
+    }
+
+    pub fn setIntegrator(&mut self, kind: IntegratorKind) {
+        self.integratorKind = kind;
+        self.integrator = kind.create();
+        self.accumulator.reset();
+    }
+
+    pub fn accumulatedPasses(&self) -> u32 {
+        self.accumulator.passes()
+    }
+
+    pub fn isConverged(&self) -> bool {
+        self.accumulatedPasses() >= self.maxAccumulatedPasses
+    }
+
+    pub fn renderHdrImageBuffer(&mut self, samplesPerPixel: u32) -> Rgb32FImage {
+        self.renderSamples(0, samplesPerPixel)
+    }
+
+    // One sample per pixel through the default display settings, as the benchmark measures it
     pub fn renderImageBuffer(&mut self) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
-        let mut imageBuffer = ImageBuffer::<Rgb<u8>, Vec<u8>>::new(self.camera.imageWidth as u32, self.camera.imageHeight as u32);
+        DisplaySettings::default().encodeImage(&self.renderHdrImageBuffer(1))
+    }
 
-        let mut rays = self.camera.getTransformedRays();
-        let totalNumRays = rays.iter().count();
+    pub fn renderSamples(&mut self, firstSampleIndex: u32, samplesPerPixel: u32) -> Rgb32FImage {
+        self.renderSamplesWith(firstSampleIndex, samplesPerPixel, || false, |_, _| {}).unwrap()
+    }
+
+    // Renders the samples [firstSampleIndex, firstSampleIndex + samplesPerPixel) of every pixel.
+    // Tiles are traced in parallel; every pixel seeds its own generator so the output does not
+    // depend on the number of threads. onTile is called from the worker threads as tiles finish,
+    // and None is returned if isCancelled fires before the image is complete.
+    pub fn renderSamplesWith<C, T>(&mut self, firstSampleIndex: u32, samplesPerPixel: u32, isCancelled: C, onTile: T) -> Option<Rgb32FImage>
+    where
+        C: Fn() -> bool + Sync,
+        T: Fn(&Tile, &[Vector3<f32>]) + Sync,
+    {
+        let sampleCount = firstSampleIndex + samplesPerPixel.max(1);
+        Some(self.renderFilmWith(firstSampleIndex, samplesPerPixel, sampleCount, isCancelled, onTile)?.image())
+    }
 
-        for y in 0..self.camera.imageHeight as u32 {
-            for x in 0..self.camera.imageWidth as u32 {
-                let i: usize = (y * self.camera.imageWidth as u32 + x) as usize;
+    // Samples are spread over the pixel area and the lens by the sampler and splatted into the film
+    // through the reconstruction filter. sampleCount is the total number of samples the image is
+    // meant to get.
+    fn renderFilmWith<C, T>(&mut self, firstSampleIndex: u32, samplesPerPixel: u32, sampleCount: u32, isCancelled: C, onTile: T) -> Option<Film>
+    where
+        C: Fn() -> bool + Sync,
+        T: Fn(&Tile, &[Vector3<f32>]) + Sync,
+    {
+        let imageWidth = self.camera.imageWidth as u32;
+        let imageHeight = self.camera.imageHeight as u32;
+        let samplesPerPixel = samplesPerPixel.max(1);
 
-                // Reverse rays to rotate the final image 180 degrees
-                let rayHit = CastRay(&self.scene, rays[totalNumRays - 1 - i]);
+        let camera = &self.camera;
+        let filter = self.filter;
+        let samplerKind = self.samplerKind;
+        let world = &self.world;
+        let integrator = &*self.integrator;
+        let tiles = GenerateTiles(imageWidth, imageHeight, TILE_SIZE);
 
-                let mut color = Rgb([0, 0, 0]);
-                if rayHit.is_some() {
-                    let hit = rayHit.unwrap().hit;
+        let renderedTiles = RenderTilesParallel(&tiles, self.threadCount, isCancelled, |tile| {
+            let mut film = Film::forTile(tile, &filter, imageWidth, imageHeight);
+            let mut sampler = samplerKind.create(sampleCount);
+            for (x, y) in tile.pixels() {
+                for sampleIndex in firstSampleIndex..firstSampleIndex + samplesPerPixel {
+                    sampler.startPixelSample(x, y, sampleIndex);
+                    let offset = sampler.next2D();
+                    let (imageX, imageY) = (x as f32 + offset.x, y as f32 + offset.y);
 
-                    color = Rgb([
-                        (255.0 * hit.Ng_x) as u8,
-                        (255.0 * hit.Ng_y) as u8,
-                        (255.0 * hit.Ng_z) as u8,
-                    ]);
+                    // Outside the image circle of a fisheye the pixel stays black
+                    let radiance = match camera.generateLensRay(imageX, imageY, &sampler.next2D()) {
+                        Some(ray) => integrator.Li(world, ray, &mut *sampler),
+                        None => Vector3::zeros(),
+                    };
+                    film.addSample(&filter, imageX, imageY, &radiance);
                 }
+            }
+
+            // Preview of the tile alone, the final pixels also get samples of neighboring tiles
+            let radiances = tile.pixels().map(|(x, y)| film.pixel(x, y)).collect::<Vec<_>>();
+            onTile(tile, &radiances);
+            film
+        })?;
+
+        let mut film = Film::new(imageWidth, imageHeight);
+        for tileFilm in renderedTiles.iter() {
+            film.merge(tileFilm);
+        }
+        Some(film)
+    }
 
-                *imageBuffer.get_pixel_mut(x, y) = color;
+    // All AOVs of the first surface seen through each pixel center, traced in one pass
+    pub fn renderAovs(&mut self) -> AovBuffers {
+        let imageWidth = self.camera.imageWidth as u32;
+        let imageHeight = self.camera.imageHeight as u32;
+        let mut aovs = AovBuffers::new(imageWidth, imageHeight);
+
+        let camera = &self.camera;
+        let forward = camera.forward();
+        let world = &self.world;
+        let tiles = GenerateTiles(imageWidth, imageHeight, TILE_SIZE);
+
+        let renderedTiles = RenderTilesParallel(&tiles, self.threadCount, || false, |tile| {
+            tile.pixels().map(|(x, y)| {
+                match camera.generateRay(x as f32 + 0.5, y as f32 + 0.5) {
+                    Some(ray) => ShadeAov(world, &ray, &forward),
+                    None => AovSample::background(),
+                }
+            }).collect::<Vec<_>>()
+        }).unwrap();
+
+        for (tile, samples) in tiles.iter().zip(renderedTiles) {
+            for ((x, y), sample) in tile.pixels().zip(samples) {
+                aovs.set(x, y, &sample);
             }
         }
 
-        imageBuffer
+        aovs
+    }
+
+    // Running average of the accumulated passes, rendering a first pass when there is none yet
+    pub fn accumulatedImage(&mut self) -> Rgb32FImage {
+        if self.accumulatedPasses() == 0 {
+            return self.renderSamples(0, 1);
+        }
+        self.accumulator.average()
+    }
+
+    // Renders one more sample pass into the accumulation buffer and returns the running average,
+    // or None when cancelled. Tiles of the first pass after a reset are forwarded to onTile.
+    pub fn accumulatePass<C, T>(&mut self, isCancelled: C, onTile: T) -> Option<Rgb32FImage>
+    where
+        C: Fn() -> bool + Sync,
+        T: Fn(&Tile, &[Vector3<f32>]) + Sync,
+    {
+        let firstSampleIndex = self.accumulator.passes();
+        let pass = self.renderFilmWith(firstSampleIndex, 1, self.maxAccumulatedPasses, isCancelled, |tile, radiances| {
+            if firstSampleIndex == 0 {
+                onTile(tile, radiances);
+            }
+        })?;
+
+        self.accumulator.addPass(&pass);
+        Some(self.accumulator.average())
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    pub fn renderNormalsToTexture(&mut self, ctx: Option<&egui::Context>) {
-        let imageBuffer = self.renderImageBuffer();
+    fn RenderDemo(threadCount: usize) -> Vec<u32> {
+        let mut renderer = Renderer::new();
+        renderer.createDemoScene();
+        renderer.camera.setRotation(&Vector3::y_axis(), 180.0_f32.to_radians());
+        renderer.camera.setTranslation(&Vector3::new(0.0, 0.0, 5.0));
+        // Several tiles with a clipped last column, and a filter that splats across tile borders
+        renderer.camera.resize(80.0, 40.0);
+        renderer.setIntegrator(IntegratorKind::PathTracing);
+        renderer.setFilter(Filter::Gaussian);
+        renderer.threadCount = threadCount;
+
+        let film = renderer.renderFilmWith(0, 4, 4, || false, |_, _| {}).unwrap();
+        film.image().as_raw().iter().map(|value| value.to_bits()).collect()
+    }
 
-        if ctx.is_some() {
-            self.renderTexture = Some(LoadEguiTextureFromImageBuffer(&ctx.unwrap(), &imageBuffer));
+    #[test]
+    fn OutputDoesNotDependOnThreadCount() {
+        let single = RenderDemo(1);
+        assert!(single.iter().any(|&bits| bits != 0));
+        for threadCount in [2, 3, 8] {
+            assert!(single == RenderDemo(threadCount), "{} threads differ from one", threadCount);
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn CameraCommandsDropAccumulatedPasses() {
+        let mut renderer = Renderer::new();
+        renderer.createDemoScene();
+        let mut camera = renderer.camera.clone();
+        camera.setRotation(&Vector3::y_axis(), 180.0_f32.to_radians());
+        camera.setTranslation(&Vector3::new(0.0, 0.0, 5.0));
+        camera.resize(16.0, 8.0);
+
+        renderer.setCamera(camera.clone());
+        for passes in 1..=2 {
+            renderer.accumulatePass(|| false, |_, _| {}).unwrap();
+            assert_eq!(renderer.accumulatedPasses(), passes);
+        }
+
+        // Even an identical camera counts as a change
+        renderer.setCamera(camera);
+        assert_eq!(renderer.accumulatedPasses(), 0);
+
+        renderer.accumulatePass(|| false, |_, _| {}).unwrap();
+        assert!(renderer.autofocus().is_some());
+        assert_eq!(renderer.accumulatedPasses(), 0);
+    }
+}
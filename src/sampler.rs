@@ -0,0 +1,549 @@
+use std::sync::OnceLock;
+
+use nalgebra::Vector2;
+
+use crate::sampling::Rng;
+
+// Largest float below one, keeps sample values in [0, 1)
+const ONE_MINUS_EPSILON: f32 = 1.0 - f32::EPSILON / 2.0;
+
+// Source of the sample values of one pixel sample. Values are drawn dimension by dimension and
+// depend only on the pixel, the sample index and the dimension, so renders are reproducible.
+pub trait Sampler {
+    // Restarts at the first dimension of the given pixel sample
+    fn startPixelSample(&mut self, x: u32, y: u32, sampleIndex: u32);
+    fn next1D(&mut self) -> f32;
+    fn next2D(&mut self) -> Vector2<f32>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SamplerKind {
+    Independent,
+    Stratified,
+    Halton,
+    Sobol,
+    BlueNoise,
+}
+
+impl SamplerKind {
+    pub const ALL: [SamplerKind; 5] = [
+        SamplerKind::Independent,
+        SamplerKind::Stratified,
+        SamplerKind::Halton,
+        SamplerKind::Sobol,
+        SamplerKind::BlueNoise,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SamplerKind::Independent => "Independent",
+            SamplerKind::Stratified => "Stratified",
+            SamplerKind::Halton => "Halton",
+            SamplerKind::Sobol => "Owen-scrambled Sobol",
+            SamplerKind::BlueNoise => "Blue noise dithered Sobol",
+        }
+    }
+
+    // Command line spelling
+    pub fn key(&self) -> &'static str {
+        match self {
+            SamplerKind::Independent => "independent",
+            SamplerKind::Stratified => "stratified",
+            SamplerKind::Halton => "halton",
+            SamplerKind::Sobol => "sobol",
+            SamplerKind::BlueNoise => "blue-noise",
+        }
+    }
+
+    pub fn fromKey(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.key() == key)
+    }
+
+    // samplesPerPixel is the number of samples the image will get in total, which the stratified
+    // sampler divides the unit square into
+    pub fn create(&self, samplesPerPixel: u32) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler::new()),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::new(samplesPerPixel)),
+            SamplerKind::Halton => Box::new(HaltonSampler::new()),
+            SamplerKind::Sobol => Box::new(SobolSampler::new()),
+            SamplerKind::BlueNoise => Box::new(BlueNoiseSampler::new()),
+        }
+    }
+}
+
+fn MixBits(mut v: u64) -> u64 {
+    v ^= v >> 31;
+    v = v.wrapping_mul(0x7fb5d329728ea185);
+    v ^= v >> 27;
+    v = v.wrapping_mul(0x81dadef4bc2dd44d);
+    v ^= v >> 33;
+    v
+}
+
+fn Hash(a: u32, b: u32) -> u32 {
+    MixBits(((a as u64) << 32) | b as u64) as u32
+}
+
+fn ToUnitFloat(bits: u32) -> f32 {
+    (bits >> 8) as f32 * (1.0 / (1u32 << 24) as f32)
+}
+
+// Element i of a pseudo-random permutation of [0, count), by Kensler 2013
+fn PermutationElement(mut i: u32, count: u32, seed: u32) -> u32 {
+    let mut w = count.wrapping_sub(1);
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < count {
+            return i.wrapping_add(seed) % count;
+        }
+    }
+}
+
+pub struct IndependentSampler {
+    rng: Rng,
+}
+
+impl IndependentSampler {
+    pub fn new() -> Self {
+        Self { rng: Rng::new(0, 0) }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn startPixelSample(&mut self, x: u32, y: u32, sampleIndex: u32) {
+        self.rng = Rng::forPixel(x, y, sampleIndex);
+    }
+
+    fn next1D(&mut self) -> f32 {
+        self.rng.nextF32()
+    }
+
+    fn next2D(&mut self) -> Vector2<f32> {
+        self.rng.next2D()
+    }
+}
+
+// Jittered strata per dimension, visited in a different random order by every pixel and dimension.
+// 2D values use the largest square grid that fits the sample count; samples beyond the count and
+// beyond the grid fall back to uniform values.
+pub struct StratifiedSampler {
+    samplesPerPixel: u32,
+    pixelSeed: u32,
+    sampleIndex: u32,
+    dimension: u32,
+    rng: Rng,
+}
+
+impl StratifiedSampler {
+    pub fn new(samplesPerPixel: u32) -> Self {
+        Self { samplesPerPixel: samplesPerPixel.max(1), pixelSeed: 0, sampleIndex: 0, dimension: 0, rng: Rng::new(0, 0) }
+    }
+
+    fn stratum(&mut self, count: u32) -> Option<u32> {
+        let seed = Hash(self.pixelSeed, self.dimension);
+        self.dimension += 1;
+        if self.sampleIndex >= count {
+            return None;
+        }
+        Some(PermutationElement(self.sampleIndex, count, seed))
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn startPixelSample(&mut self, x: u32, y: u32, sampleIndex: u32) {
+        self.pixelSeed = Hash(x, y);
+        self.sampleIndex = sampleIndex;
+        self.dimension = 0;
+        self.rng = Rng::forPixel(x, y, sampleIndex);
+    }
+
+    fn next1D(&mut self) -> f32 {
+        let count = self.samplesPerPixel;
+        let jitter = self.rng.nextF32();
+        match self.stratum(count) {
+            Some(stratum) => ((stratum as f32 + jitter) / count as f32).min(ONE_MINUS_EPSILON),
+            None => jitter,
+        }
+    }
+
+    fn next2D(&mut self) -> Vector2<f32> {
+        let strata = (self.samplesPerPixel as f32).sqrt() as u32;
+        let jitter = self.rng.next2D();
+        match self.stratum(strata * strata) {
+            Some(stratum) => {
+                let cell = Vector2::new((stratum % strata) as f32, (stratum / strata) as f32);
+                ((cell + jitter) / strata as f32).map(|value| value.min(ONE_MINUS_EPSILON))
+            }
+            None => jitter,
+        }
+    }
+}
+
+const HALTON_PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+    59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131,
+];
+
+fn RadicalInverse(base: u32, mut index: u32) -> f32 {
+    let inverseBase = 1.0 / base as f64;
+    let mut reversed = 0u64;
+    let mut factor = 1.0;
+    while index > 0 {
+        let next = index / base;
+        reversed = reversed * base as u64 + (index - next * base) as u64;
+        factor *= inverseBase;
+        index = next;
+    }
+    ((reversed as f64 * factor) as f32).min(ONE_MINUS_EPSILON)
+}
+
+// Halton sequence in the first 32 prime bases, decorrelated between pixels by a random toroidal
+// shift (Cranley-Patterson rotation). Dimensions past the last base are uniform.
+pub struct HaltonSampler {
+    pixelSeed: u32,
+    sampleIndex: u32,
+    dimension: u32,
+    rng: Rng,
+}
+
+impl HaltonSampler {
+    pub fn new() -> Self {
+        Self { pixelSeed: 0, sampleIndex: 0, dimension: 0, rng: Rng::new(0, 0) }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn startPixelSample(&mut self, x: u32, y: u32, sampleIndex: u32) {
+        self.pixelSeed = Hash(x, y);
+        self.sampleIndex = sampleIndex;
+        self.dimension = 0;
+        self.rng = Rng::forPixel(x, y, sampleIndex);
+    }
+
+    fn next1D(&mut self) -> f32 {
+        let dimension = self.dimension;
+        self.dimension += 1;
+
+        match HALTON_PRIMES.get(dimension as usize) {
+            Some(&base) => {
+                let shift = ToUnitFloat(Hash(self.pixelSeed, dimension));
+                (RadicalInverse(base, self.sampleIndex) + shift).fract().min(ONE_MINUS_EPSILON)
+            }
+            None => self.rng.nextF32(),
+        }
+    }
+
+    fn next2D(&mut self) -> Vector2<f32> {
+        Vector2::new(self.next1D(), self.next1D())
+    }
+}
+
+fn LaineKarrasPermutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+// Owen scrambling of all bits at once, by Burley 2020
+fn NestedUniformScramble(x: u32, seed: u32) -> u32 {
+    LaineKarrasPermutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+// First two Sobol dimensions; the first is the van der Corput sequence, the second uses the
+// direction numbers of the polynomial x + 1
+fn Sobol2D(index: u32) -> (u32, u32) {
+    let mut first = 0;
+    let mut second = 0;
+    let mut direction = 1u32 << 31;
+    for bit in 0..32 {
+        if (index >> bit) & 1 == 1 {
+            first ^= 1u32 << (31 - bit);
+            second ^= direction;
+        }
+        direction ^= direction >> 1;
+    }
+    (first, second)
+}
+
+// Shuffled and Owen-scrambled Sobol points (Burley 2020). Every draw takes a 2D Sobol point with
+// its own index shuffle and scrambles, so dimensions stay decorrelated however many are used.
+fn ScrambledSobol2D(sampleIndex: u32, seed: u32) -> Vector2<f32> {
+    let index = NestedUniformScramble(sampleIndex, seed);
+    let (first, second) = Sobol2D(index);
+    Vector2::new(
+        ToUnitFloat(NestedUniformScramble(first, Hash(seed, 0))),
+        ToUnitFloat(NestedUniformScramble(second, Hash(seed, 1))),
+    )
+}
+
+pub struct SobolSampler {
+    pixelSeed: u32,
+    sampleIndex: u32,
+    dimension: u32,
+}
+
+impl SobolSampler {
+    pub fn new() -> Self {
+        Self { pixelSeed: 0, sampleIndex: 0, dimension: 0 }
+    }
+
+    fn nextSeed(&mut self) -> u32 {
+        self.dimension += 1;
+        Hash(self.pixelSeed, self.dimension)
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn startPixelSample(&mut self, x: u32, y: u32, sampleIndex: u32) {
+        self.pixelSeed = Hash(x, y);
+        self.sampleIndex = sampleIndex;
+        self.dimension = 0;
+    }
+
+    fn next1D(&mut self) -> f32 {
+        let seed = self.nextSeed();
+        ScrambledSobol2D(self.sampleIndex, seed).x
+    }
+
+    fn next2D(&mut self) -> Vector2<f32> {
+        let seed = self.nextSeed();
+        ScrambledSobol2D(self.sampleIndex, seed)
+    }
+}
+
+const BLUE_NOISE_SIZE: u32 = 64;
+
+// Tileable blue noise ranks in [0, 1) made with the void and cluster method of Ulichney 1993
+fn BlueNoiseTexture() -> &'static [f32] {
+    static TEXTURE: OnceLock<Vec<f32>> = OnceLock::new();
+    TEXTURE.get_or_init(|| GenerateBlueNoise(BLUE_NOISE_SIZE as usize, 1.5))
+}
+
+fn GenerateBlueNoise(size: usize, sigma: f32) -> Vec<f32> {
+    let count = size * size;
+
+    // Gaussian energy of a point on the torus, indexed by the wrapped offset
+    let kernel: Vec<f32> = (0..count).map(|i| {
+        let (dx, dy) = (i % size, i / size);
+        let (dx, dy) = (dx.min(size - dx) as f32, dy.min(size - dy) as f32);
+        (-(dx * dx + dy * dy) / (2.0 * sigma * sigma)).exp()
+    }).collect();
+
+    let mut pattern = vec![false; count];
+    let mut energy = vec![0.0f32; count];
+    let toggle = |pattern: &mut Vec<bool>, energy: &mut Vec<f32>, index: usize| {
+        pattern[index] = !pattern[index];
+        let sign = if pattern[index] { 1.0 } else { -1.0 };
+        let (x, y) = (index % size, index / size);
+        for (j, value) in energy.iter_mut().enumerate() {
+            let dx = (j % size + size - x) % size;
+            let dy = (j / size + size - y) % size;
+            *value += sign * kernel[dy * size + dx];
+        }
+    };
+
+    // Tightest cluster among the set points, or largest void among the empty ones
+    let tightestCluster = |pattern: &[bool], energy: &[f32]| {
+        (0..count).filter(|&i| pattern[i]).max_by(|&a, &b| energy[a].total_cmp(&energy[b])).unwrap()
+    };
+    let largestVoid = |pattern: &[bool], energy: &[f32]| {
+        (0..count).filter(|&i| !pattern[i]).min_by(|&a, &b| energy[a].total_cmp(&energy[b])).unwrap()
+    };
+
+    // Random initial points, spread out by moving points from clusters into voids until stable
+    let initialCount = count / 10;
+    let mut rng = Rng::new(0x5eed, 0);
+    let mut placed = 0;
+    while placed < initialCount {
+        let index = rng.nextU32() as usize % count;
+        if !pattern[index] {
+            toggle(&mut pattern, &mut energy, index);
+            placed += 1;
+        }
+    }
+    for _ in 0..count {
+        let cluster = tightestCluster(&pattern, &energy);
+        toggle(&mut pattern, &mut energy, cluster);
+        let void = largestVoid(&pattern, &energy);
+        toggle(&mut pattern, &mut energy, void);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut ranks = vec![0; count];
+
+    // Ranks below the initial points come from removing them cluster by cluster
+    let (mut removePattern, mut removeEnergy) = (pattern.clone(), energy.clone());
+    for rank in (0..initialCount).rev() {
+        let cluster = tightestCluster(&removePattern, &removeEnergy);
+        toggle(&mut removePattern, &mut removeEnergy, cluster);
+        ranks[cluster] = rank;
+    }
+
+    // The rest fills the largest voids. Past half, the largest void of the set points is also
+    // the tightest cluster of the empty ones, so one rule covers both phases.
+    for rank in initialCount..count {
+        let void = largestVoid(&pattern, &energy);
+        toggle(&mut pattern, &mut energy, void);
+        ranks[void] = rank;
+    }
+
+    ranks.iter().map(|&rank| (rank as f32 + 0.5) / count as f32).collect()
+}
+
+// Sobol points shared by all pixels, shifted per pixel by a blue noise value (Georgiev and
+// Fajardo 2016). Neighboring pixels get very different shifts, which turns the error of low
+// sample counts into high frequency noise. Every dimension reads the texture at another offset.
+pub struct BlueNoiseSampler {
+    x: u32,
+    y: u32,
+    sampleIndex: u32,
+    dimension: u32,
+}
+
+impl BlueNoiseSampler {
+    pub fn new() -> Self {
+        Self { x: 0, y: 0, sampleIndex: 0, dimension: 0 }
+    }
+
+    fn shift(&self, dimension: u32, component: u32) -> f32 {
+        let offset = Hash(dimension, component);
+        let x = (self.x + (offset & 0xffff)) % BLUE_NOISE_SIZE;
+        let y = (self.y + (offset >> 16)) % BLUE_NOISE_SIZE;
+        BlueNoiseTexture()[(y * BLUE_NOISE_SIZE + x) as usize]
+    }
+
+    fn nextDimension(&mut self) -> (u32, Vector2<f32>) {
+        self.dimension += 1;
+        (self.dimension, ScrambledSobol2D(self.sampleIndex, Hash(0xb1e, self.dimension)))
+    }
+}
+
+impl Sampler for BlueNoiseSampler {
+    fn startPixelSample(&mut self, x: u32, y: u32, sampleIndex: u32) {
+        self.x = x;
+        self.y = y;
+        self.sampleIndex = sampleIndex;
+        self.dimension = 0;
+    }
+
+    fn next1D(&mut self) -> f32 {
+        let (dimension, point) = self.nextDimension();
+        (point.x + self.shift(dimension, 0)).fract().min(ONE_MINUS_EPSILON)
+    }
+
+    fn next2D(&mut self) -> Vector2<f32> {
+        let (dimension, point) = self.nextDimension();
+        Vector2::new(
+            (point.x + self.shift(dimension, 0)).fract().min(ONE_MINUS_EPSILON),
+            (point.y + self.shift(dimension, 1)).fract().min(ONE_MINUS_EPSILON),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A few draws in the mix of dimensions an integrator uses
+    fn Draws(sampler: &mut dyn Sampler, x: u32, y: u32, sampleIndex: u32) -> Vec<f32> {
+        sampler.startPixelSample(x, y, sampleIndex);
+        let mut values = Vec::new();
+        for _ in 0..20 {
+            let u = sampler.next2D();
+            values.extend([sampler.next1D(), u.x, u.y]);
+        }
+        values
+    }
+
+    #[test]
+    fn SamePixelSampleGivesTheSameSequence() {
+        for kind in SamplerKind::ALL {
+            let mut sampler = kind.create(16);
+            let first = Draws(&mut *sampler, 3, 5, 7);
+            assert!(first.iter().all(|value| (0.0..1.0).contains(value)), "{}", kind.name());
+
+            // Independent of what the sampler did before and of the instance
+            let other = Draws(&mut *sampler, 4, 5, 7);
+            Draws(&mut *sampler, 3, 5, 8);
+            assert_eq!(first, Draws(&mut *sampler, 3, 5, 7), "{}", kind.name());
+            assert_eq!(first, Draws(&mut *kind.create(16), 3, 5, 7), "{}", kind.name());
+            assert_ne!(first, other, "{}", kind.name());
+        }
+    }
+
+    // Checks that the points fill every cell of a columns x rows grid exactly once
+    fn AssertOnePointPerCell(points: &[Vector2<f32>], columns: u32, rows: u32, what: &str) {
+        let mut cells: Vec<u32> = points.iter()
+            .map(|point| (point.y * rows as f32) as u32 * columns + (point.x * columns as f32) as u32)
+            .collect();
+        cells.sort();
+        assert_eq!(cells, (0..columns * rows).collect::<Vec<_>>(), "{} in {}x{} cells", what, columns, rows);
+    }
+
+    fn PixelSamples(kind: SamplerKind, count: u32, dimension: usize) -> Vec<Vector2<f32>> {
+        let mut sampler = kind.create(count);
+        (0..count).map(|sampleIndex| {
+            sampler.startPixelSample(11, 2, sampleIndex);
+            (0..dimension).for_each(|_| { sampler.next2D(); });
+            sampler.next2D()
+        }).collect()
+    }
+
+    #[test]
+    fn StratifiedSamplesFillEveryStratum() {
+        for dimension in [0, 3] {
+            AssertOnePointPerCell(&PixelSamples(SamplerKind::Stratified, 16, dimension), 4, 4, "stratified");
+        }
+
+        let mut sampler = StratifiedSampler::new(10);
+        let values: Vec<f32> = (0..10).map(|sampleIndex| {
+            sampler.startPixelSample(11, 2, sampleIndex);
+            sampler.next1D()
+        }).collect();
+        AssertOnePointPerCell(&values.iter().map(|&value| Vector2::new(value, 0.0)).collect::<Vec<_>>(), 10, 1, "stratified 1D");
+    }
+
+    #[test]
+    fn SobolSamplesFormNets() {
+        // Every power of two prefix has one point in each elementary interval of its area
+        for count in [4, 16, 64] {
+            for dimension in [0, 5] {
+                let points = PixelSamples(SamplerKind::Sobol, count, dimension);
+                let mut columns = 1;
+                while columns <= count {
+                    AssertOnePointPerCell(&points, columns, count / columns, "sobol");
+                    columns *= 2;
+                }
+            }
+        }
+    }
+}
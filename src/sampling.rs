@@ -0,0 +1,198 @@
+use std::f32::consts::PI;
+
+use nalgebra::{Vector2, Vector3};
+
+// Small PCG32 generator, good enough for Monte Carlo integration and cheap to seed per pixel
+#[derive(Clone, Copy, Debug)]
+pub struct Rng {
+    state: u64,
+    increment: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64, stream: u64) -> Self {
+        let mut rng = Self {
+            state: 0,
+            increment: (stream << 1) | 1,
+        };
+        rng.nextU32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.nextU32();
+        rng
+    }
+
+    // Seed a generator for a single pixel sample so the result does not depend on render order
+    pub fn forPixel(x: u32, y: u32, sampleIndex: u32) -> Self {
+        Self::new(((y as u64) << 32) | x as u64, sampleIndex as u64)
+    }
+
+    pub fn nextU32(&mut self) -> u32 {
+        let oldState = self.state;
+        self.state = oldState
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(self.increment);
+        let xorShifted = (((oldState >> 18) ^ oldState) >> 27) as u32;
+        let rotation = (oldState >> 59) as u32;
+        xorShifted.rotate_right(rotation)
+    }
+
+    // Uniform float in [0, 1)
+    pub fn nextF32(&mut self) -> f32 {
+        (self.nextU32() >> 8) as f32 * (1.0 / (1u32 << 24) as f32)
+    }
+
+    pub fn next2D(&mut self) -> Vector2<f32> {
+        Vector2::new(self.nextF32(), self.nextF32())
+    }
+}
+
+// Builds two tangents perpendicular to a unit normal (Duff et al. 2017)
+pub fn BuildOrthonormalBasis(normal: &Vector3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+    let sign = 1.0_f32.copysign(normal.z);
+    let a = -1.0 / (sign + normal.z);
+    let b = normal.x * normal.y * a;
+
+    let tangent = Vector3::new(1.0 + sign * normal.x * normal.x * a, sign * b, -sign * normal.x);
+    let bitangent = Vector3::new(b, sign + normal.y * normal.y * a, -normal.y);
+
+    (tangent, bitangent)
+}
+
+pub fn ToWorld(local: &Vector3<f32>, normal: &Vector3<f32>) -> Vector3<f32> {
+    let (tangent, bitangent) = BuildOrthonormalBasis(normal);
+    tangent * local.x + bitangent * local.y + normal * local.z
+}
+
+pub fn ToLocal(direction: &Vector3<f32>, normal: &Vector3<f32>) -> Vector3<f32> {
+    let (tangent, bitangent) = BuildOrthonormalBasis(normal);
+    Vector3::new(direction.dot(&tangent), direction.dot(&bitangent), direction.dot(normal))
+}
+
+// Cosine weighted direction around +z, pdf = cos(theta) / PI
+pub fn SampleCosineHemisphere(u: &Vector2<f32>) -> Vector3<f32> {
+    let radius = u.x.sqrt();
+    let phi = 2.0 * PI * u.y;
+    let z = (1.0 - u.x).max(0.0).sqrt();
+    Vector3::new(radius * phi.cos(), radius * phi.sin(), z)
+}
+
+// Uniform point on the unit disk, keeps the strata of u intact (Shirley and Chiu 1997)
+pub fn SampleConcentricDisk(u: &Vector2<f32>) -> Vector2<f32> {
+    let offset = u * 2.0 - Vector2::new(1.0, 1.0);
+    if offset.x == 0.0 && offset.y == 0.0 {
+        return Vector2::zeros();
+    }
+
+    let (radius, theta) = if offset.x.abs() > offset.y.abs() {
+        (offset.x, PI / 4.0 * (offset.y / offset.x))
+    } else {
+        (offset.y, PI / 2.0 - PI / 4.0 * (offset.x / offset.y))
+    };
+    Vector2::new(theta.cos(), theta.sin()) * radius
+}
+
+// Uniform point inside the regular polygon with the given number of sides and unit circumradius,
+// with its first vertex on the +x axis
+pub fn SampleRegularPolygon(u: &Vector2<f32>, sides: u32) -> Vector2<f32> {
+    let sides = sides.max(3);
+    let scaled = u.x * sides as f32;
+    let wedge = (scaled as u32).min(sides - 1);
+    let remainder = scaled - wedge as f32;
+
+    let vertex = |index: u32| {
+        let angle = 2.0 * PI * index as f32 / sides as f32;
+        Vector2::new(angle.cos(), angle.sin())
+    };
+
+    // Uniform point in the triangle between the center and the two vertices of the wedge
+    let s = remainder.sqrt();
+    vertex(wedge) * (s * (1.0 - u.y)) + vertex(wedge + 1) * (s * u.y)
+}
+
+// Multiple importance sampling weight of a strategy with density pdf against another one
+pub fn PowerHeuristic(pdf: f32, otherPdf: f32) -> f32 {
+    let (a, b) = (pdf * pdf, otherPdf * otherPdf);
+    if a + b <= 0.0 { 0.0 } else { a / (a + b) }
+}
+
+// Piecewise constant distribution over [0, 1) proportional to the given non-negative values
+pub struct Distribution1D {
+    function: Vec<f32>,
+    cdf: Vec<f32>,
+    integral: f32,
+}
+
+impl Distribution1D {
+    pub fn new(function: Vec<f32>) -> Self {
+        let count = function.len().max(1);
+        let mut cdf = vec![0.0; count + 1];
+        for i in 0..function.len() {
+            cdf[i + 1] = cdf[i] + function[i].max(0.0) / count as f32;
+        }
+        let integral = cdf[count];
+
+        // Fall back to a uniform distribution when everything is zero
+        for (i, value) in cdf.iter_mut().enumerate() {
+            *value = if integral > 0.0 { *value / integral } else { i as f32 / count as f32 };
+        }
+
+        Self { function, cdf, integral }
+    }
+
+    pub fn count(&self) -> usize {
+        self.function.len()
+    }
+
+    pub fn integral(&self) -> f32 {
+        self.integral
+    }
+
+    // Returns the sampled position in [0, 1), its density and the index of its segment
+    pub fn sampleContinuous(&self, u: f32) -> (f32, f32, usize) {
+        let count = self.count().max(1);
+        let index = self.cdf.partition_point(|&value| value <= u).saturating_sub(1).min(count - 1);
+        let width = self.cdf[index + 1] - self.cdf[index];
+        let offset = if width > 0.0 { ((u - self.cdf[index]) / width).clamp(0.0, 1.0) } else { 0.5 };
+        let position = ((index as f32 + offset) / count as f32).min(1.0 - f32::EPSILON);
+        (position, self.pdf(index), index)
+    }
+
+    // Density of the segment at index, with respect to [0, 1)
+    pub fn pdf(&self, index: usize) -> f32 {
+        if self.integral > 0.0 {
+            self.function.get(index).map_or(0.0, |value| value.max(0.0) / self.integral)
+        } else {
+            1.0
+        }
+    }
+}
+
+// Piecewise constant distribution over [0, 1)^2 from a row major grid of values
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,   // one per row
+    marginal: Distribution1D,           // over rows
+}
+
+impl Distribution2D {
+    pub fn new(values: &[f32], width: usize, height: usize) -> Self {
+        let conditional: Vec<Distribution1D> = (0..height)
+            .map(|y| Distribution1D::new(values[y * width..(y + 1) * width].to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(conditional.iter().map(|row| row.integral()).collect());
+        Self { conditional, marginal }
+    }
+
+    pub fn sample(&self, u: &Vector2<f32>) -> (Vector2<f32>, f32) {
+        let (y, marginalPdf, row) = self.marginal.sampleContinuous(u.y);
+        let (x, conditionalPdf, _) = self.conditional[row].sampleContinuous(u.x);
+        (Vector2::new(x, y), marginalPdf * conditionalPdf)
+    }
+
+    pub fn pdf(&self, uv: &Vector2<f32>) -> f32 {
+        let height = self.conditional.len();
+        let row = ((uv.y * height as f32) as usize).min(height.saturating_sub(1));
+        let conditional = &self.conditional[row];
+        let column = ((uv.x * conditional.count() as f32) as usize).min(conditional.count().saturating_sub(1));
+        self.marginal.pdf(row) * conditional.pdf(column)
+    }
+}
@@ -0,0 +1,638 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use nalgebra::{Matrix4, Rotation3, Vector3};
+use serde::Deserialize;
+
+use crate::calibration::LoadCalibration;
+use crate::camera::ApertureShape;
+use crate::distortion::Distortion;
+use crate::light::{DirectionalLight, PointLight, SpotLight};
+use crate::loader::{LoadModel, LoadOptions};
+use crate::material::Material;
+use crate::projection::Projection;
+use crate::renderer::Renderer;
+use crate::texture::{ColorSpace, TextureCache};
+use crate::world::DEFAULT_MATERIAL;
+
+// Declarative scene description, see ParseSceneFile for the TOML layout:
+//
+//     [camera]
+//     position = [0.0, 1.0, 5.0]
+//     look_at = [0.0, 0.0, 0.0]
+//     vertical_fov = 45.0
+//     width = 640
+//     height = 480
+//     aperture_radius = 0.05          # thin lens depth of field, 0 for a pinhole
+//     aperture_blades = 6             # polygonal aperture, circular when left out
+//     focus_distance = 4.0            # or autofocus = true to focus on the image center
+//     projection = "fisheye-equisolid" # perspective when left out, see Projection::key
+//     fisheye_fov = 180.0             # image circle field of view of the fisheye projections
+//     ortho_height = 4.0              # view height of the orthographic projection
+//     distortion = "brown-conrady"    # or "kannala-brandt", applies to the perspective projection
+//     distortion_coefficients = [-0.28, 0.07, 0.0002, 0.00002]  # OpenCV order, k1, k2, p1, p2[, k3[, k4, k5, k6]]
+//     calibration = "camera.yaml"     # OpenCV or ROS calibration instead of vertical_fov and distortion
+//
+//     [[materials]]
+//     name = "white"
+//     diffuse = [0.8, 0.8, 0.8]
+//     diffuse_texture = "textures/wood.png"  # sRGB, replaces diffuse, its alpha scales opacity
+//     roughness = 0.5                 # also specular, metalness, emission, opacity, ior and transmission
+//
+//     [[meshes]]
+//     file = "models/bunny.obj"       # relative to the scene file
+//     material = "white"
+//     translation = [0.0, -1.0, 0.0]
+//     rotation = [0.0, 90.0, 0.0]     # euler angles in degrees around x, y, z
+//     scale = 2.0
+//
+//     [[spheres]]
+//     center = [0.0, 0.0, 0.0]
+//     radius = 1.0
+//
+//     [[lights]]
+//     type = "sphere"                 # emits from its surface, like meshes with emissive materials
+//     center = [0.0, 4.0, 0.0]
+//     radius = 0.5
+//     emission = [10.0, 10.0, 10.0]
+//
+//     [environment]
+//     file = "sky.hdr"                # equirectangular .hdr or .exr, lights the scene
+//     intensity = 1.0
+//     rotation = 0.0                  # degrees around the up axis
+//
+//     [[lights]]
+//     type = "spot"                   # or "point" (position only) and "directional" (direction only)
+//     position = [0.0, 4.0, 0.0]
+//     direction = [0.0, -1.0, 0.0]
+//     intensity = [50.0, 50.0, 50.0]  # irradiance for directional lights
+//     inner_angle = 20.0              # degrees from the axis, full intensity inside
+//     outer_angle = 30.0              # degrees from the axis, dark outside
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct SceneDescription {
+    pub camera: Option<CameraDescription>,
+    #[serde(default)]
+    pub materials: Vec<MaterialDescription>,
+    #[serde(default)]
+    pub meshes: Vec<MeshDescription>,
+    #[serde(default)]
+    pub spheres: Vec<SphereDescription>,
+    #[serde(default)]
+    pub lights: Vec<LightDescription>,
+    pub environment: Option<EnvironmentDescription>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct CameraDescription {
+    pub position: [f32; 3],
+    pub look_at: Option<[f32; 3]>,
+    pub up: Option<[f32; 3]>,
+    pub rotation: Option<[f32; 3]>,
+    pub vertical_fov: Option<f32>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub aperture_radius: Option<f32>,
+    pub aperture_blades: Option<u32>,
+    pub focus_distance: Option<f32>,
+    #[serde(default)]
+    pub autofocus: bool,
+    pub projection: Option<String>,
+    pub fisheye_fov: Option<f32>,
+    pub ortho_height: Option<f32>,
+    pub distortion: Option<String>,
+    pub distortion_coefficients: Option<Vec<f32>>,
+    pub calibration: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct MaterialDescription {
+    pub name: String,
+    pub diffuse: Option<[f32; 3]>,
+    pub diffuse_texture: Option<PathBuf>,
+    pub specular: Option<[f32; 3]>,
+    pub roughness: Option<f32>,
+    pub metalness: Option<f32>,
+    pub emission: Option<[f32; 3]>,
+    pub opacity: Option<f32>,
+    pub transmission: Option<f32>,
+    pub ior: Option<f32>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(untagged)]
+pub enum Scale {
+    Uniform(f32),
+    PerAxis([f32; 3]),
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct MeshDescription {
+    pub file: PathBuf,
+    pub material: Option<String>,
+    pub translation: Option<[f32; 3]>,
+    pub rotation: Option<[f32; 3]>,
+    pub scale: Option<Scale>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SphereDescription {
+    pub center: [f32; 3],
+    pub radius: f32,
+    pub material: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct LightDescription {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub center: Option<[f32; 3]>,
+    pub radius: Option<f32>,
+    pub emission: Option<[f32; 3]>,
+    pub position: Option<[f32; 3]>,
+    pub direction: Option<[f32; 3]>,
+    pub intensity: Option<[f32; 3]>,
+    pub inner_angle: Option<f32>,
+    pub outer_angle: Option<f32>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct EnvironmentDescription {
+    pub file: PathBuf,
+    pub intensity: Option<f32>,
+    pub rotation: Option<f32>,
+}
+
+#[derive(Debug)]
+pub enum SceneFileError {
+    Io { path: PathBuf, error: io::Error },
+    Parse { path: PathBuf, error: toml::de::Error },
+    Invalid { entry: String, message: String },
+}
+
+impl fmt::Display for SceneFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneFileError::Io { path, error } => write!(f, "cannot read scene file '{}': {}", path.display(), error),
+            SceneFileError::Parse { path, error } => write!(f, "invalid scene file '{}': {}", path.display(), error),
+            SceneFileError::Invalid { entry, message } => write!(f, "invalid {}: {}", entry, message),
+        }
+    }
+}
+
+impl std::error::Error for SceneFileError {}
+
+fn Invalid(entry: impl Into<String>, message: impl Into<String>) -> SceneFileError {
+    SceneFileError::Invalid { entry: entry.into(), message: message.into() }
+}
+
+fn ToVector(values: &[f32; 3]) -> Vector3<f32> {
+    Vector3::new(values[0], values[1], values[2])
+}
+
+// Finite and greater than zero, NaN fails every check
+fn IsPositive(value: f32) -> bool {
+    value.is_finite() && value > 0.0
+}
+
+fn IsNonNegative(value: f32) -> bool {
+    value.is_finite() && value >= 0.0
+}
+
+fn IsColor(values: &[f32; 3]) -> bool {
+    values.iter().all(|&value| IsNonNegative(value))
+}
+
+fn IsFinite(values: &[f32; 3]) -> bool {
+    values.iter().all(|value| value.is_finite())
+}
+
+// 'a', 'b' or 'c'
+fn ExpectedKeys(keys: &[&str]) -> String {
+    let quoted: Vec<String> = keys.iter().map(|key| format!("'{}'", key)).collect();
+    match quoted.split_last() {
+        Some((last, [])) => last.clone(),
+        Some((last, rest)) => format!("{} or {}", rest.join(", "), last),
+        None => String::new(),
+    }
+}
+
+fn ComposeTransform(translation: Option<[f32; 3]>, rotation: Option<[f32; 3]>, scale: Option<Scale>) -> Matrix4<f32> {
+    let translation = ToVector(&translation.unwrap_or([0.0; 3]));
+    let [rx, ry, rz] = rotation.unwrap_or([0.0; 3]);
+    let rotation = Rotation3::from_euler_angles(rx.to_radians(), ry.to_radians(), rz.to_radians());
+    let scale = match scale.unwrap_or(Scale::Uniform(1.0)) {
+        Scale::Uniform(s) => Vector3::new(s, s, s),
+        Scale::PerAxis(s) => ToVector(&s),
+    };
+
+    Matrix4::new_translation(&translation) * rotation.to_homogeneous() * Matrix4::new_nonuniform_scaling(&scale)
+}
+
+pub fn ParseSceneFile(path: &Path) -> Result<SceneDescription, SceneFileError> {
+    let text = fs::read_to_string(path).map_err(|error| SceneFileError::Io { path: path.to_path_buf(), error })?;
+    toml::from_str(&text).map_err(|error| SceneFileError::Parse { path: path.to_path_buf(), error })
+}
+
+impl SceneDescription {
+    // Adds everything in the description to the renderer's world and sets up its camera.
+    // Relative mesh paths are resolved against baseDirectory.
+    pub fn build(&self, renderer: &mut Renderer, baseDirectory: &Path) -> Result<(), SceneFileError> {
+        let mut materialIndices: HashMap<&str, usize> = HashMap::new();
+        let mut textures = TextureCache::default();
+        for material in &self.materials {
+            let entry = format!("material '{}'", material.name);
+            if materialIndices.contains_key(material.name.as_str()) {
+                return Err(Invalid(entry, "defined more than once"));
+            }
+
+            let defaults = Material::default();
+            let diffuse = material.diffuse.unwrap_or([0.8; 3]);
+            let specular = material.specular.unwrap_or([0.0; 3]);
+            let emission = material.emission.unwrap_or([0.0; 3]);
+            if !IsColor(&diffuse) || diffuse.iter().any(|value| *value > 1.0) {
+                return Err(Invalid(entry, "diffuse components must be within [0, 1]"));
+            }
+            if !IsColor(&specular) || specular.iter().any(|value| *value > 1.0) {
+                return Err(Invalid(entry, "specular components must be within [0, 1]"));
+            }
+            if !IsColor(&emission) {
+                return Err(Invalid(entry, "emission components must be non-negative"));
+            }
+            for (key, value) in [("roughness", material.roughness), ("metalness", material.metalness), ("opacity", material.opacity), ("transmission", material.transmission)] {
+                if value.is_some_and(|value| !(0.0..=1.0).contains(&value)) {
+                    return Err(Invalid(entry, format!("{} must be within [0, 1]", key)));
+                }
+            }
+            if material.ior.is_some_and(|ior| !(IsPositive(ior) && ior >= 1.0)) {
+                return Err(Invalid(entry, "ior must be at least 1"));
+            }
+
+            let index = renderer.world.addMaterial(Material {
+                name: material.name.clone(),
+                diffuse: ToVector(&diffuse),
+                diffuseTexture: material.diffuse_texture.as_ref()
+                    .and_then(|texture| textures.load(&baseDirectory.join(texture), ColorSpace::Srgb)),
+                specular: ToVector(&specular),
+                roughness: material.roughness.unwrap_or(defaults.roughness),
+                metalness: material.metalness.unwrap_or(defaults.metalness),
+                emission: ToVector(&emission),
+                opacity: material.opacity.unwrap_or(defaults.opacity),
+                transmission: material.transmission.unwrap_or(defaults.transmission),
+                ior: material.ior.unwrap_or(defaults.ior),
+                ..defaults
+            });
+            materialIndices.insert(material.name.as_str(), index);
+        }
+
+        let lookupMaterial = |entry: &str, name: &Option<String>| -> Result<usize, SceneFileError> {
+            match name {
+                Some(name) => materialIndices.get(name.as_str()).copied()
+                    .ok_or_else(|| Invalid(entry, format!("unknown material '{}'", name))),
+                None => Ok(DEFAULT_MATERIAL),
+            }
+        };
+
+        for (i, mesh) in self.meshes.iter().enumerate() {
+            let entry = format!("meshes[{}]", i);
+            let material = lookupMaterial(&entry, &mesh.material)?;
+            if [mesh.translation, mesh.rotation].iter().flatten().any(|values| !IsFinite(values)) {
+                return Err(Invalid(entry, "translation and rotation must be finite"));
+            }
+            let transform = ComposeTransform(mesh.translation, mesh.rotation, mesh.scale);
+            let determinant = transform.determinant();
+            if determinant.is_nan() || determinant.abs() <= f32::EPSILON {
+                return Err(Invalid(entry, "transform is degenerate"));
+            }
+
+            let path = baseDirectory.join(&mesh.file);
+            let materialOverride = mesh.material.as_ref().map(|_| material);
+            LoadModel(&mut renderer.world, &path, &transform, materialOverride, &LoadOptions::default())
+                .map_err(|error| Invalid(entry, error.to_string()))?;
+        }
+
+        for (i, sphere) in self.spheres.iter().enumerate() {
+            let entry = format!("spheres[{}]", i);
+            if !IsFinite(&sphere.center) {
+                return Err(Invalid(entry, "center must be finite"));
+            }
+            if !IsPositive(sphere.radius) {
+                return Err(Invalid(entry, "radius must be positive"));
+            }
+            let material = lookupMaterial(&entry, &sphere.material)?;
+            let [x, y, z] = sphere.center;
+            renderer.world.addSphere((x, y, z), sphere.radius, material);
+        }
+
+        for (i, light) in self.lights.iter().enumerate() {
+            let entry = format!("lights[{}]", i);
+            let require = |value: Option<[f32; 3]>, key: &str| {
+                value.ok_or_else(|| Invalid(&entry, format!("{} lights need {}", light.kind, key)))
+            };
+            let requireColor = |value: Option<[f32; 3]>, key: &str| {
+                let color = require(value, key)?;
+                if !IsColor(&color) {
+                    return Err(Invalid(&entry, format!("{} components must be non-negative", key)));
+                }
+                Ok(ToVector(&color))
+            };
+            let requirePosition = |value: Option<[f32; 3]>| {
+                let position = require(value, "a position")?;
+                if !IsFinite(&position) {
+                    return Err(Invalid(&entry, "position must be finite"));
+                }
+                Ok(ToVector(&position))
+            };
+            let requireDirection = |value: Option<[f32; 3]>| {
+                let direction = require(value, "a direction")?;
+                if !IsFinite(&direction) {
+                    return Err(Invalid(&entry, "direction must be finite"));
+                }
+                ToVector(&direction).try_normalize(f32::EPSILON)
+                    .ok_or_else(|| Invalid(&entry, "direction must not be zero"))
+            };
+
+            match light.kind.as_str() {
+                "sphere" => {
+                    let center = light.center.ok_or_else(|| Invalid(&entry, "sphere lights need a center"))?;
+                    let radius = light.radius.ok_or_else(|| Invalid(&entry, "sphere lights need a radius"))?;
+                    if !IsFinite(&center) {
+                        return Err(Invalid(entry, "center must be finite"));
+                    }
+                    if !IsPositive(radius) {
+                        return Err(Invalid(entry, "radius must be positive"));
+                    }
+
+                    let material = renderer.world.addMaterial(Material {
+                        name: entry.clone(),
+                        diffuse: Vector3::zeros(),
+                        emission: requireColor(light.emission, "emission")?,
+                        ..Material::default()
+                    });
+                    renderer.world.addSphere((center[0], center[1], center[2]), radius, material);
+                }
+                "point" => {
+                    renderer.world.addLight(Box::new(PointLight {
+                        position: requirePosition(light.position)?,
+                        intensity: requireColor(light.intensity, "intensity")?,
+                    }));
+                }
+                "directional" => {
+                    renderer.world.addLight(Box::new(DirectionalLight {
+                        direction: requireDirection(light.direction)?,
+                        irradiance: requireColor(light.intensity, "intensity")?,
+                    }));
+                }
+                "spot" => {
+                    let outerAngle = light.outer_angle.unwrap_or(30.0);
+                    let innerAngle = light.inner_angle.unwrap_or(outerAngle);
+                    if !(IsPositive(outerAngle) && outerAngle <= 180.0 && IsNonNegative(innerAngle) && innerAngle <= outerAngle) {
+                        return Err(Invalid(entry, "spot angles must satisfy 0 <= inner_angle <= outer_angle <= 180"));
+                    }
+
+                    renderer.world.addLight(Box::new(SpotLight {
+                        position: requirePosition(light.position)?,
+                        direction: requireDirection(light.direction)?,
+                        intensity: requireColor(light.intensity, "intensity")?,
+                        cosInnerAngle: innerAngle.to_radians().cos(),
+                        cosOuterAngle: outerAngle.to_radians().cos(),
+                    }));
+                }
+                other => return Err(Invalid(entry, format!(
+                    "unsupported light type '{}', expected {}", other, ExpectedKeys(&["sphere", "point", "directional", "spot"]),
+                ))),
+            }
+        }
+
+        if let Some(environment) = &self.environment {
+            let entry = "environment";
+            let intensity = environment.intensity.unwrap_or(1.0);
+            if !IsNonNegative(intensity) {
+                return Err(Invalid(entry, "intensity must be non-negative"));
+            }
+            let rotation = environment.rotation.unwrap_or(0.0);
+            if !rotation.is_finite() {
+                return Err(Invalid(entry, "rotation must be finite"));
+            }
+            renderer.loadEnvironment(&baseDirectory.join(&environment.file), intensity, rotation)
+                .map_err(|error| Invalid(entry, format!("cannot load '{}': {}", environment.file.display(), error)))?;
+        }
+
+        // Autofocus traces the committed world
+        renderer.commitWorld();
+
+        if let Some(camera) = &self.camera {
+            camera.apply(renderer, baseDirectory)?;
+        }
+        Ok(())
+    }
+}
+
+impl CameraDescription {
+    fn apply(&self, renderer: &mut Renderer, baseDirectory: &Path) -> Result<(), SceneFileError> {
+        let entry = "camera";
+        if [Some(self.position), self.look_at, self.up, self.rotation].iter().flatten().any(|values| !IsFinite(values)) {
+            return Err(Invalid(entry, "position, look_at, up and rotation must be finite"));
+        }
+        let position = ToVector(&self.position);
+
+        match (self.look_at, self.rotation) {
+            (Some(_), Some(_)) => return Err(Invalid(entry, "look_at and rotation are mutually exclusive")),
+            (Some(target), None) => {
+                let target = ToVector(&target);
+                let up = ToVector(&self.up.unwrap_or([0.0, 1.0, 0.0]));
+                let forward = target - position;
+                if forward.norm() <= f32::EPSILON || forward.cross(&up).norm() <= f32::EPSILON {
+                    return Err(Invalid(entry, "look_at must differ from position and not be parallel to up"));
+                }
+                renderer.camera.lookAt(&position, &target, &up);
+            }
+            (None, rotation) => {
+                renderer.camera.setTransform(ComposeTransform(Some(self.position), rotation, None));
+            }
+        }
+
+        if let Some(verticalFov) = self.vertical_fov {
+            if !(IsPositive(verticalFov) && verticalFov < 180.0) {
+                return Err(Invalid(entry, "vertical_fov must be within (0, 180) degrees"));
+            }
+            renderer.camera.setFov(verticalFov);
+        }
+
+        // The calibrated resolution is the default, width and height rescale the intrinsics
+        if let Some(calibration) = &self.calibration {
+            if self.vertical_fov.is_some() || self.distortion.is_some() || self.distortion_coefficients.is_some() {
+                return Err(Invalid(entry, "calibration excludes vertical_fov and distortion"));
+            }
+            let calibration = LoadCalibration(&baseDirectory.join(calibration)).map_err(|error| Invalid(entry, error.to_string()))?;
+            if !calibration.apply(&mut renderer.camera) {
+                return Err(Invalid(entry, "calibration does not describe a valid camera"));
+            }
+        }
+
+        let width = self.width.unwrap_or(renderer.camera.imageWidth as u32);
+        let height = self.height.unwrap_or(renderer.camera.imageHeight as u32);
+        if width == 0 || height == 0 {
+            return Err(Invalid(entry, "width and height must be positive"));
+        }
+        renderer.camera.resize(width as f32, height as f32);
+
+        let radius = self.aperture_radius.unwrap_or(0.0);
+        if !IsNonNegative(radius) {
+            return Err(Invalid(entry, "aperture_radius must be non-negative"));
+        }
+        let shape = match self.aperture_blades {
+            None => ApertureShape::Circular,
+            Some(blades) if blades >= 3 => ApertureShape::Polygonal { blades },
+            Some(_) => return Err(Invalid(entry, "aperture_blades must be at least 3")),
+        };
+        renderer.camera.setAperture(radius, shape);
+
+        let projection = match &self.projection {
+            Some(key) => Projection::fromKey(key).ok_or_else(|| {
+                let keys: Vec<&str> = Projection::ALL.iter().map(Projection::key).collect();
+                Invalid(entry, format!("unknown projection '{}', expected {}", key, ExpectedKeys(&keys)))
+            })?,
+            None => Projection::Perspective,
+        };
+        let projection = match (projection, self.fisheye_fov, self.ortho_height) {
+            (_, Some(fov), _) if !(IsPositive(fov) && fov <= 360.0) => {
+                return Err(Invalid(entry, "fisheye_fov must be within (0, 360] degrees"));
+            }
+            (_, _, Some(height)) if !IsPositive(height) => {
+                return Err(Invalid(entry, "ortho_height must be positive"));
+            }
+            (Projection::FisheyeEquidistant { .. }, Some(fov), _) => Projection::FisheyeEquidistant { fov },
+            (Projection::FisheyeEquisolid { .. }, Some(fov), _) => Projection::FisheyeEquisolid { fov },
+            (Projection::Orthographic { .. }, _, Some(height)) => Projection::Orthographic { height },
+            (projection, _, _) => projection,
+        };
+        renderer.camera.setProjection(projection);
+
+        let coefficients = self.distortion_coefficients.clone().unwrap_or_default();
+        if coefficients.iter().any(|c| !c.is_finite()) {
+            return Err(Invalid(entry, "distortion_coefficients must be finite"));
+        }
+        let distortion = Distortion::fromCoefficients(self.distortion.as_deref().unwrap_or("none"), &coefficients).ok_or_else(|| Invalid(
+            entry,
+            "distortion must be none, brown-conrady with 4, 5 or 8 coefficients or kannala-brandt with 4",
+        ))?;
+        if self.calibration.is_none() {
+            renderer.camera.setDistortion(distortion);
+        }
+
+        match (self.focus_distance, self.autofocus) {
+            (Some(_), true) => return Err(Invalid(entry, "focus_distance and autofocus are mutually exclusive")),
+            (Some(distance), false) => {
+                if !IsPositive(distance) {
+                    return Err(Invalid(entry, "focus_distance must be positive"));
+                }
+                renderer.camera.setFocusDistance(distance);
+            }
+            (None, true) => {
+                if renderer.camera.autofocus(&renderer.world).is_none() {
+                    eprintln!("warning: autofocus found no surface at the image center");
+                }
+            }
+            (None, false) => {}
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The example at the top of this file, without the comment markers
+    fn DocumentedExample() -> String {
+        include_str!("scene_file.rs").lines()
+            .skip_while(|line| !line.starts_with("// Declarative scene description"))
+            .skip(1)
+            .take_while(|line| line.starts_with("//"))
+            .map(|line| line.trim_start_matches("//").trim_start())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn Build(text: &str) -> Result<(), String> {
+        let description: SceneDescription = toml::from_str(text).map_err(|error| error.to_string())?;
+        description.build(&mut Renderer::new(), Path::new(".")).map_err(|error| error.to_string())
+    }
+
+    #[test]
+    fn DocumentedExampleParses() {
+        let description: SceneDescription = toml::from_str(&DocumentedExample()).unwrap();
+
+        let camera = description.camera.unwrap();
+        assert_eq!(camera.position, [0.0, 1.0, 5.0]);
+        assert_eq!(camera.look_at, Some([0.0, 0.0, 0.0]));
+        assert_eq!(camera.projection.as_deref(), Some("fisheye-equisolid"));
+        assert_eq!(camera.distortion_coefficients.map(|c| c.len()), Some(4));
+
+        assert_eq!(description.materials.len(), 1);
+        assert_eq!(description.materials[0].diffuse_texture, Some(PathBuf::from("textures/wood.png")));
+        assert!(matches!(description.meshes[0].scale, Some(Scale::Uniform(scale)) if scale == 2.0));
+        assert_eq!(description.spheres.len(), 1);
+        let kinds: Vec<&str> = description.lights.iter().map(|light| light.kind.as_str()).collect();
+        assert_eq!(kinds, ["sphere", "spot"]);
+        assert_eq!(description.environment.unwrap().file, PathBuf::from("sky.hdr"));
+    }
+
+    #[test]
+    fn RejectsUnknownFields() {
+        for text in [
+            "[camera]\nposition = [0.0, 0.0, 5.0]\nfov = 45.0",
+            "[[spheres]]\ncenter = [0.0, 0.0, 0.0]\nradius = 1.0\ncolour = [1.0, 0.0, 0.0]",
+            "[[materials]]\nname = \"red\"\nalbedo = [1.0, 0.0, 0.0]",
+            "[sky]\ncolor = [1.0, 1.0, 1.0]",
+        ] {
+            assert!(toml::from_str::<SceneDescription>(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn LookAtExcludesRotation() {
+        let error = Build("[camera]\nposition = [0.0, 0.0, 5.0]\nlook_at = [0.0, 0.0, 0.0]\nrotation = [0.0, 180.0, 0.0]").unwrap_err();
+        assert_eq!(error, "invalid camera: look_at and rotation are mutually exclusive");
+        assert!(Build("[camera]\nposition = [0.0, 0.0, 5.0]\nlook_at = [0.0, 0.0, 0.0]").is_ok());
+        assert!(Build("[camera]\nposition = [0.0, 0.0, 5.0]\nrotation = [0.0, 180.0, 0.0]").is_ok());
+    }
+
+    #[test]
+    fn RejectsNonFiniteNumbers() {
+        for (text, message) in [
+            ("[[spheres]]\ncenter = [nan, 0.0, 0.0]\nradius = 1.0", "invalid spheres[0]: center must be finite"),
+            ("[[spheres]]\ncenter = [0.0, 0.0, 0.0]\nradius = inf", "invalid spheres[0]: radius must be positive"),
+            ("[[meshes]]\nfile = \"missing.obj\"\ntranslation = [0.0, nan, 0.0]", "invalid meshes[0]: translation and rotation must be finite"),
+            ("[[meshes]]\nfile = \"missing.obj\"\nrotation = [0.0, 0.0, inf]", "invalid meshes[0]: translation and rotation must be finite"),
+            ("[[meshes]]\nfile = \"missing.obj\"\nscale = nan", "invalid meshes[0]: transform is degenerate"),
+            ("[[lights]]\ntype = \"point\"\nposition = [0.0, inf, 0.0]\nintensity = [1.0, 1.0, 1.0]", "invalid lights[0]: position must be finite"),
+            ("[[lights]]\ntype = \"directional\"\ndirection = [0.0, nan, -1.0]\nintensity = [1.0, 1.0, 1.0]", "invalid lights[0]: direction must be finite"),
+            ("[environment]\nfile = \"sky.hdr\"\nrotation = nan", "invalid environment: rotation must be finite"),
+            ("[camera]\nposition = [0.0, 0.0, -inf]", "invalid camera: position, look_at, up and rotation must be finite"),
+        ] {
+            assert_eq!(Build(text).unwrap_err(), message, "{}", text);
+        }
+    }
+
+    #[test]
+    fn UnknownProjectionNamesTheChoices() {
+        let error = Build("[camera]\nposition = [0.0, 0.0, 5.0]\nprojection = \"fisheye\"").unwrap_err();
+        assert_eq!(
+            error,
+            "invalid camera: unknown projection 'fisheye', expected 'perspective', 'orthographic', 'fisheye-equidistant', \
+             'fisheye-equisolid', 'equirectangular' or 'cubemap'"
+        );
+    }
+}
@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use image::{ImageError, Rgba, Rgba32FImage};
+use nalgebra::{Vector2, Vector4};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ColorSpace {
+    Srgb,       // color textures, decoded to linear on load
+    Linear,     // data textures such as normal and roughness maps
+}
+
+pub fn SrgbToLinear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// Mip-mapped RGBA texture with repeat wrapping
+pub struct Texture {
+    pub path: PathBuf,
+    mips: Vec<Rgba32FImage>,
+    opaque: bool,       // alpha is 1 everywhere
+}
+
+impl fmt::Debug for Texture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Texture({}, {}x{}, {} levels)", self.path.display(), self.width(), self.height(), self.mips.len())
+    }
+}
+
+// Halves an image with a 2x2 box filter, odd edges reuse the last texel
+fn Downsample(image: &Rgba32FImage) -> Rgba32FImage {
+    let width = (image.width() / 2).max(1);
+    let height = (image.height() / 2).max(1);
+
+    Rgba32FImage::from_fn(width, height, |x, y| {
+        let mut sum = [0.0; 4];
+        for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            let texel = image.get_pixel((2 * x + dx).min(image.width() - 1), (2 * y + dy).min(image.height() - 1));
+            for (total, value) in sum.iter_mut().zip(texel.0) {
+                *total += value * 0.25;
+            }
+        }
+        Rgba(sum)
+    })
+}
+
+impl Texture {
+    pub fn load(path: &Path, colorSpace: ColorSpace) -> Result<Self, ImageError> {
+        let mut image = image::open(path)?.to_rgba32f();
+        if colorSpace == ColorSpace::Srgb {
+            for pixel in image.pixels_mut() {
+                for channel in 0..3 {
+                    pixel.0[channel] = SrgbToLinear(pixel.0[channel]);
+                }
+            }
+        }
+        Ok(Self::fromImage(path.to_path_buf(), image))
+    }
+
+    pub fn fromImage(path: PathBuf, image: Rgba32FImage) -> Self {
+        let opaque = image.pixels().all(|pixel| pixel.0[3] >= 1.0);
+        let mut mips = vec![image];
+        while let Some(last) = mips.last() {
+            if last.width() == 1 && last.height() == 1 {
+                break;
+            }
+            let next = Downsample(last);
+            mips.push(next);
+        }
+        Self { path, mips, opaque }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.mips[0].width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.mips[0].height()
+    }
+
+    pub fn isOpaque(&self) -> bool {
+        self.opaque
+    }
+
+    // Level of detail offset that turns a uv-space footprint into a texel footprint
+    pub fn lodBias(&self) -> f32 {
+        0.5 * ((self.width() * self.height()) as f32).log2()
+    }
+
+    fn texel(&self, level: usize, x: i64, y: i64) -> Vector4<f32> {
+        let image = &self.mips[level];
+        let x = x.rem_euclid(image.width() as i64) as u32;
+        let y = y.rem_euclid(image.height() as i64) as u32;
+        Vector4::from(image.get_pixel(x, y).0)
+    }
+
+    // Bilinear lookup in a single mip level. v points up as in assimp, image rows go down.
+    pub fn sampleBilinear(&self, level: usize, uv: &Vector2<f32>) -> Vector4<f32> {
+        let level = level.min(self.mips.len() - 1);
+        let image = &self.mips[level];
+
+        let x = uv.x * image.width() as f32 - 0.5;
+        let y = (1.0 - uv.y) * image.height() as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let top = self.texel(level, x0, y0) * (1.0 - fx) + self.texel(level, x0 + 1, y0) * fx;
+        let bottom = self.texel(level, x0, y0 + 1) * (1.0 - fx) + self.texel(level, x0 + 1, y0 + 1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+
+    // Trilinear lookup, lod is the log2 of the footprint in texels of the finest level
+    pub fn sample(&self, uv: &Vector2<f32>, lod: f32) -> Vector4<f32> {
+        if !uv.x.is_finite() || !uv.y.is_finite() {
+            return self.sampleBilinear(0, &Vector2::zeros());
+        }
+
+        let lod = if lod.is_finite() { lod.clamp(0.0, (self.mips.len() - 1) as f32) } else { 0.0 };
+        let level = lod.floor() as usize;
+        let fraction = lod - level as f32;
+
+        if fraction <= 0.0 || level + 1 >= self.mips.len() {
+            return self.sampleBilinear(level, uv);
+        }
+        self.sampleBilinear(level, uv) * (1.0 - fraction) + self.sampleBilinear(level + 1, uv) * fraction
+    }
+}
+
+// Loads every texture file once; failures are reported and cached as missing
+#[derive(Default)]
+pub struct TextureCache {
+    textures: HashMap<(PathBuf, ColorSpace), Option<Arc<Texture>>>,
+}
+
+impl TextureCache {
+    pub fn load(&mut self, path: &Path, colorSpace: ColorSpace) -> Option<Arc<Texture>> {
+        self.textures.entry((path.to_path_buf(), colorSpace))
+            .or_insert_with(|| match Texture::load(path, colorSpace) {
+                Ok(texture) => Some(Arc::new(texture)),
+                Err(error) => {
+                    eprintln!("warning: cannot load texture '{}': {}", path.display(), error);
+                    None
+                }
+            })
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Texture coordinates of the center of texel (x, y) of a level
+    fn TexelCenter(texture: &Texture, level: usize, x: u32, y: u32) -> Vector2<f32> {
+        let image = &texture.mips[level];
+        Vector2::new((x as f32 + 0.5) / image.width() as f32, 1.0 - (y as f32 + 0.5) / image.height() as f32)
+    }
+
+    #[test]
+    fn ConstantTextureSamplesToConstantAtEveryLevel() {
+        let color = Vector4::new(0.25, 0.5, 0.75, 1.0);
+        let texture = Texture::fromImage(PathBuf::new(), Rgba32FImage::from_pixel(5, 3, Rgba(color.into())));
+        assert_eq!(texture.mips.iter().map(|mip| mip.dimensions()).collect::<Vec<_>>(), [(5, 3), (2, 1), (1, 1)]);
+
+        for uv in [Vector2::new(0.0, 0.0), Vector2::new(0.3, 0.7), Vector2::new(-1.2, 2.9), Vector2::new(0.999, 0.001)] {
+            for level in 0..4 {
+                assert!((texture.sampleBilinear(level, &uv) - color).norm() < 1e-6);
+            }
+            for lod in [0.0, 0.4, 1.5, 2.0, 7.0] {
+                assert!((texture.sample(&uv, lod) - color).norm() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn CheckerLevelsAverage() {
+        // Checker of 2x2 texel squares, black and white
+        let image = Rgba32FImage::from_fn(8, 8, |x, y| {
+            let value = ((x / 2 + y / 2) % 2) as f32;
+            Rgba([value, value, value, 1.0])
+        });
+        let texture = Texture::fromImage(PathBuf::new(), image);
+        assert_eq!(texture.mips.len(), 4);
+
+        // Level 1 still resolves the squares, every coarser level is their average
+        for y in 0..4 {
+            for x in 0..4 {
+                let expected = ((x + y) % 2) as f32;
+                assert!((texture.sampleBilinear(1, &TexelCenter(&texture, 1, x, y)).x - expected).abs() < 1e-6);
+            }
+        }
+        for level in 2..4 {
+            let size = 8 >> level;
+            for y in 0..size {
+                for x in 0..size {
+                    assert!((texture.sampleBilinear(level, &TexelCenter(&texture, level, x, y)).x - 0.5).abs() < 1e-6);
+                }
+            }
+        }
+
+        // Halfway between levels 1 and 2 blends them
+        let uv = TexelCenter(&texture, 1, 1, 0);
+        assert!((texture.sample(&uv, 1.5).x - 0.75).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,75 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+pub const TILE_SIZE: u32 = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Tile {
+    pub fn pixels(&self) -> impl Iterator<Item=(u32, u32)> + '_ {
+        (self.y..self.y + self.height).flat_map(move |y| (self.x..self.x + self.width).map(move |x| (x, y)))
+    }
+}
+
+// Splits the image into row-major tiles, clipping the last row and column to the image bounds
+pub fn GenerateTiles(imageWidth: u32, imageHeight: u32, tileSize: u32) -> Vec<Tile> {
+    let mut tiles = vec![];
+    for y in (0..imageHeight).step_by(tileSize as usize) {
+        for x in (0..imageWidth).step_by(tileSize as usize) {
+            tiles.push(Tile {
+                x,
+                y,
+                width: tileSize.min(imageWidth - x),
+                height: tileSize.min(imageHeight - y),
+            });
+        }
+    }
+    tiles
+}
+
+pub fn DefaultThreadCount() -> usize {
+    thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
+// Renders every tile exactly once on a pool of scoped threads; results are returned in tile order.
+// Workers stop picking up new tiles once isCancelled returns true, in which case None is returned.
+pub fn RenderTilesParallel<T, F, C>(tiles: &[Tile], threadCount: usize, isCancelled: C, renderTile: F) -> Option<Vec<T>>
+where
+    T: Send,
+    F: Fn(&Tile) -> T + Sync,
+    C: Fn() -> bool + Sync,
+{
+    let nextTile = AtomicUsize::new(0);
+    let threadCount = threadCount.clamp(1, tiles.len().max(1));
+
+    let mut rendered: Vec<(usize, T)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threadCount).map(|_| {
+            scope.spawn(|| {
+                let mut finished = vec![];
+                while !isCancelled() {
+                    let index = nextTile.fetch_add(1, Ordering::Relaxed);
+                    if index >= tiles.len() {
+                        break;
+                    }
+                    finished.push((index, renderTile(&tiles[index])));
+                }
+                finished
+            })
+        }).collect();
+
+        workers.into_iter().flat_map(|worker| worker.join().unwrap()).collect()
+    });
+
+    if rendered.len() < tiles.len() {
+        return None;
+    }
+
+    rendered.sort_by_key(|(index, _)| *index);
+    Some(rendered.into_iter().map(|(_, result)| result).collect())
+}
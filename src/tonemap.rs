@@ -0,0 +1,200 @@
+use image::{ImageBuffer, Rgb, Rgb32FImage};
+use nalgebra::{Matrix3, Vector3};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ToneMapper {
+    Clamp,
+    Reinhard,
+    AcesFilmic,
+    AgX,
+}
+
+impl ToneMapper {
+    pub const ALL: [ToneMapper; 4] = [
+        ToneMapper::Clamp,
+        ToneMapper::Reinhard,
+        ToneMapper::AcesFilmic,
+        ToneMapper::AgX,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ToneMapper::Clamp => "Clamp",
+            ToneMapper::Reinhard => "Reinhard",
+            ToneMapper::AcesFilmic => "ACES filmic",
+            ToneMapper::AgX => "AgX",
+        }
+    }
+
+    // Command line spelling
+    pub fn key(&self) -> &'static str {
+        match self {
+            ToneMapper::Clamp => "clamp",
+            ToneMapper::Reinhard => "reinhard",
+            ToneMapper::AcesFilmic => "aces",
+            ToneMapper::AgX => "agx",
+        }
+    }
+
+    pub fn fromKey(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|mapper| mapper.key() == key)
+    }
+
+    // Scene linear radiance to display linear values in [0, 1]
+    pub fn apply(&self, color: &Vector3<f32>) -> Vector3<f32> {
+        let color = color.map(|value| if value.is_finite() { value.max(0.0) } else { 0.0 });
+        let mapped = match self {
+            ToneMapper::Clamp => color,
+            ToneMapper::Reinhard => color.map(|value| value / (1.0 + value)),
+            ToneMapper::AcesFilmic => AcesFitted(&color),
+            ToneMapper::AgX => AgX(&color),
+        };
+        mapped.map(|value| value.clamp(0.0, 1.0))
+    }
+}
+
+// RRT + ODT fit by Stephen Hill, including the sRGB to ACES working space conversions
+fn AcesFitted(color: &Vector3<f32>) -> Vector3<f32> {
+    let input = Matrix3::new(
+        0.59719, 0.35458, 0.04823,
+        0.07600, 0.90834, 0.01566,
+        0.02840, 0.13383, 0.83777,
+    );
+    let output = Matrix3::new(
+        1.60475, -0.53108, -0.07367,
+        -0.10208, 1.10813, -0.00605,
+        -0.00327, -0.07276, 1.07602,
+    );
+
+    let v = input * color;
+    let fitted = v.map(|x| (x * (x + 0.0245786) - 0.000090537) / (x * (0.983729 * x + 0.432951) + 0.238081));
+    output * fitted
+}
+
+// Minimal AgX by Benjamin Wrensch, with the default look
+fn AgX(color: &Vector3<f32>) -> Vector3<f32> {
+    const MIN_EV: f32 = -12.47393;
+    const MAX_EV: f32 = 4.026069;
+
+    let inset = Matrix3::from_column_slice(&[
+        0.84247905, 0.042328242, 0.042375654,
+        0.0784336, 0.87846863, 0.0784336,
+        0.079223745, 0.07916613, 0.879143,
+    ]);
+    let outset = Matrix3::from_column_slice(&[
+        1.196879, -0.052896854, -0.052971635,
+        -0.09802088, 1.1519032, -0.09804345,
+        -0.09902974, -0.098961174, 1.1510737,
+    ]);
+
+    let v = (inset * color).map(|x| ((x.max(1e-10).log2() - MIN_EV) / (MAX_EV - MIN_EV)).clamp(0.0, 1.0));
+
+    // Sigmoid contrast curve approximation
+    let v = v.map(|x| {
+        let x2 = x * x;
+        let x4 = x2 * x2;
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232
+    });
+
+    (outset * v).map(|x| x.max(0.0).powf(2.2))
+}
+
+pub fn LinearToSrgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// Transfer function from display linear values to the encoded output
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    Srgb,
+    Gamma(f32),
+    Linear,
+}
+
+impl Encoding {
+    pub fn apply(&self, value: f32) -> f32 {
+        match self {
+            Encoding::Srgb => LinearToSrgb(value),
+            Encoding::Gamma(gamma) => value.powf(1.0 / gamma),
+            Encoding::Linear => value,
+        }
+    }
+}
+
+// Everything between the linear framebuffer and the 8 bit image shown or saved
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DisplaySettings {
+    pub toneMapper: ToneMapper,
+    pub exposure: f32,      // in EV, every step doubles the brightness
+    pub encoding: Encoding,
+}
+
+impl Default for DisplaySettings {
+    fn default() -> Self {
+        Self {
+            toneMapper: ToneMapper::Clamp,
+            exposure: 0.0,
+            encoding: Encoding::Srgb,
+        }
+    }
+}
+
+impl DisplaySettings {
+    pub fn apply(&self, radiance: &Vector3<f32>) -> Vector3<f32> {
+        let mapped = self.toneMapper.apply(&(radiance * self.exposure.exp2()));
+        mapped.map(|value| self.encoding.apply(value))
+    }
+
+    pub fn encodeImage(&self, hdrImageBuffer: &Rgb32FImage) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+        ImageBuffer::from_fn(hdrImageBuffer.width(), hdrImageBuffer.height(), |x, y| {
+            let [r, g, b] = hdrImageBuffer.get_pixel(x, y).0;
+            let encoded = self.apply(&Vector3::new(r, g, b));
+            Rgb([
+                (255.0 * encoded.x + 0.5) as u8,
+                (255.0 * encoded.y + 0.5) as u8,
+                (255.0 * encoded.z + 0.5) as u8,
+            ])
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ToneMappersAreMonotonicAndBounded() {
+        for mapper in ToneMapper::ALL {
+            assert_eq!(mapper.apply(&Vector3::zeros()), Vector3::zeros(), "{}", mapper.name());
+
+            let mut previous = Vector3::zeros();
+            for i in 1..=2000 {
+                let gray = 1e-4 * 1.01_f32.powi(i);
+                let mapped = mapper.apply(&Vector3::repeat(gray));
+                assert!(mapped.iter().all(|&value| (0.0..=1.0).contains(&value)), "{} {} {:?}", mapper.name(), gray, mapped);
+                assert!(mapped.iter().zip(previous.iter()).all(|(value, last)| value >= &(last - 1e-6)), "{} {}", mapper.name(), gray);
+                previous = mapped;
+            }
+
+            for color in [Vector3::new(1e6, 0.0, 0.5), Vector3::new(-1.0, 3.0, 0.1), Vector3::new(f32::NAN, f32::INFINITY, 2.0)] {
+                let mapped = mapper.apply(&color);
+                assert!(mapped.iter().all(|&value| (0.0..=1.0).contains(&value)), "{} {:?}", mapper.name(), mapped);
+            }
+        }
+    }
+
+    #[test]
+    fn LinearToSrgbMatchesTheReference() {
+        assert_eq!(LinearToSrgb(0.0), 0.0);
+        assert!((LinearToSrgb(1.0) - 1.0).abs() < 1e-6);
+        // Both pieces meet at the breakpoint, 0.0031308 in linear and 0.04045 encoded
+        assert!((LinearToSrgb(0.0031308) - 0.04045).abs() < 1e-5);
+        assert!((LinearToSrgb(0.0031309) - 0.04045).abs() < 1e-5);
+        assert!((LinearToSrgb(0.18) - 0.461356).abs() < 1e-5);
+        assert!((LinearToSrgb(0.5) - 0.735357).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,304 @@
+use std::path::PathBuf;
+
+use eframe::{egui, App};
+use eframe::egui::{CentralPanel, Image, Rect, TextureHandle, Ui};
+use image::{ImageBuffer, Rgb, Rgb32FImage};
+
+use crate::camera::{ApertureShape, Camera};
+use crate::export::HasExtension;
+use crate::film::Filter;
+use crate::integrator::IntegratorKind;
+use crate::projection::Projection;
+use crate::render_thread::{RenderThread, RenderUpdate};
+use crate::renderer::{CreateEguiColorImageFromImageBuffer, QuantizeImageBuffer, Renderer};
+use crate::sampler::SamplerKind;
+use crate::tonemap::{DisplaySettings, Encoding, ToneMapper};
+
+fn LoadEguiTextureFromImageBuffer(ctx: &egui::Context, imageBuffer: &ImageBuffer<Rgb<u8>, Vec<u8>>) -> TextureHandle {
+    let eguiColorImage = CreateEguiColorImageFromImageBuffer(imageBuffer);
+    ctx.load_texture("", eguiColorImage, Default::default())
+}
+
+// egui front end, rendering happens on a RenderThread so the UI never waits for a pass
+pub struct Viewer {
+    pub renderTexture: Option<TextureHandle>,
+    pub camera: Camera,
+    integratorKind: IntegratorKind,
+    samplerKind: SamplerKind,
+    filter: Filter,
+    renderThread: RenderThread,
+    displayBuffer: Rgb32FImage,     // linear radiance, tone mapped only for display
+    displayDirty: bool,
+    display: DisplaySettings,
+    passes: u32,
+    savePath: String,
+    saveStatus: String,
+}
+
+impl Viewer {
+    pub fn new(renderer: Renderer, ctx: &egui::Context) -> Self {
+        let camera = renderer.camera.clone();
+        let integratorKind = renderer.integratorKind;
+        let samplerKind = renderer.samplerKind;
+        let filter = renderer.filter;
+
+        Self {
+            renderTexture: None,
+            displayBuffer: Rgb32FImage::new(camera.imageWidth as u32, camera.imageHeight as u32),
+            camera,
+            integratorKind,
+            samplerKind,
+            filter,
+            renderThread: RenderThread::spawn(renderer, ctx.clone()),
+            displayDirty: false,
+            display: DisplaySettings::default(),
+            passes: 0,
+            savePath: String::from("render.exr"),
+            saveStatus: String::new(),
+        }
+    }
+
+    // Forwards the current camera to the render thread, cancelling the pass in flight
+    pub fn cameraChanged(&mut self) {
+        self.renderThread.setCamera(self.camera.clone());
+        self.resetDisplay();
+    }
+
+    pub fn setIntegrator(&mut self, kind: IntegratorKind) {
+        self.integratorKind = kind;
+        self.renderThread.setIntegrator(kind);
+        self.resetDisplay();
+    }
+
+    pub fn setSampler(&mut self, kind: SamplerKind) {
+        self.samplerKind = kind;
+        self.renderThread.setSampler(kind);
+        self.resetDisplay();
+    }
+
+    pub fn setFilter(&mut self, filter: Filter) {
+        self.filter = filter;
+        self.renderThread.setFilter(filter);
+        self.resetDisplay();
+    }
+
+    fn resetDisplay(&mut self) {
+        let (width, height) = (self.camera.imageWidth as u32, self.camera.imageHeight as u32);
+        if self.displayBuffer.dimensions() != (width, height) {
+            self.displayBuffer = Rgb32FImage::new(width, height);
+        }
+        self.passes = 0;
+    }
+
+    fn receiveUpdates(&mut self) {
+        let mut generation = self.renderThread.generation();
+
+        while let Some(update) = self.renderThread.tryRecv() {
+            match update {
+                RenderUpdate::Tile { generation: tileGeneration, tile, radiances } => {
+                    if tileGeneration != generation || self.passes > 0 {
+                        continue;
+                    }
+                    for ((x, y), radiance) in tile.pixels().zip(radiances) {
+                        if x < self.displayBuffer.width() && y < self.displayBuffer.height() {
+                            *self.displayBuffer.get_pixel_mut(x, y) = Rgb([radiance.x, radiance.y, radiance.z]);
+                        }
+                    }
+                    self.displayDirty = true;
+                }
+                RenderUpdate::Pass { generation: passGeneration, passes, image } => {
+                    if passGeneration != generation {
+                        continue;
+                    }
+                    self.displayBuffer = image;
+                    self.passes = passes;
+                    self.displayDirty = true;
+                }
+                RenderUpdate::Focused { generation: focusGeneration, distance } => {
+                    // Nothing at the image center, the focus stays where it was
+                    let Some(distance) = distance else { continue };
+                    // The render thread already uses the new focus unless a camera sent after the
+                    // autofocus replaced it, then the focused camera goes out again
+                    self.camera.setFocusDistance(distance);
+                    if focusGeneration != generation {
+                        self.cameraChanged();
+                        generation = self.renderThread.generation();
+                    }
+                }
+                RenderUpdate::Saved { path, result } => {
+                    self.saveStatus = match result {
+                        Ok(()) => format!("saved {}", path.display()),
+                        Err(error) => format!("failed to write '{}': {}", path.display(), error),
+                    };
+                }
+            }
+        }
+    }
+}
+
+impl App for Viewer {
+    fn update(&mut self, ctx: &egui::Context, _: &mut eframe::Frame) {
+        CentralPanel::default().show(ctx, |ui: &mut Ui| {
+            let size = ui.min_rect().max;
+
+            if size.x != self.camera.imageWidth || size.y != self.camera.imageHeight {
+                self.camera.resize(size.x, size.y);
+                self.cameraChanged();
+            }
+
+            self.receiveUpdates();
+            if self.displayDirty {
+                let imageBuffer = QuantizeImageBuffer(&self.displayBuffer, &self.display);
+                self.renderTexture = Some(LoadEguiTextureFromImageBuffer(ctx, &imageBuffer));
+                self.displayDirty = false;
+            }
+
+            ui.horizontal(|ui| {
+                ui.label("Hello bro");
+
+                let mut integratorKind = self.integratorKind;
+                egui::ComboBox::from_label("Integrator")
+                    .selected_text(integratorKind.name())
+                    .show_ui(ui, |ui| {
+                        for kind in IntegratorKind::ALL {
+                            ui.selectable_value(&mut integratorKind, kind, kind.name());
+                        }
+                    });
+                if integratorKind != self.integratorKind {
+                    self.setIntegrator(integratorKind);
+                }
+
+                let mut samplerKind = self.samplerKind;
+                egui::ComboBox::from_label("Sampler")
+                    .selected_text(samplerKind.name())
+                    .show_ui(ui, |ui| {
+                        for kind in SamplerKind::ALL {
+                            ui.selectable_value(&mut samplerKind, kind, kind.name());
+                        }
+                    });
+                if samplerKind != self.samplerKind {
+                    self.setSampler(samplerKind);
+                }
+
+                let mut filter = self.filter;
+                egui::ComboBox::from_label("Filter")
+                    .selected_text(filter.name())
+                    .show_ui(ui, |ui| {
+                        for option in Filter::ALL {
+                            ui.selectable_value(&mut filter, option, option.name());
+                        }
+                    });
+                if filter != self.filter {
+                    self.setFilter(filter);
+                }
+
+                ui.label(format!("{} passes", self.passes));
+            });
+
+            // Display settings only change how the framebuffer is shown, the render goes on
+            ui.horizontal(|ui| {
+                let previous = self.display;
+
+                egui::ComboBox::from_label("Tone mapping")
+                    .selected_text(self.display.toneMapper.name())
+                    .show_ui(ui, |ui| {
+                        for mapper in ToneMapper::ALL {
+                            ui.selectable_value(&mut self.display.toneMapper, mapper, mapper.name());
+                        }
+                    });
+                ui.add(egui::Slider::new(&mut self.display.exposure, -10.0..=10.0).step_by(0.1).text("Exposure (EV)"));
+
+                let mut srgb = self.display.encoding == Encoding::Srgb;
+                ui.checkbox(&mut srgb, "sRGB");
+                self.display.encoding = if srgb { Encoding::Srgb } else { Encoding::Linear };
+
+                if self.display != previous {
+                    self.displayDirty = true;
+                    ctx.request_repaint();
+                }
+            });
+
+            // Thin lens depth of field, a radius of zero is a pinhole camera
+            ui.horizontal(|ui| {
+                let previous = (self.camera.apertureRadius, self.camera.apertureShape, self.camera.focusDistance);
+                let (mut radius, mut focusDistance) = (previous.0, previous.2);
+                let mut blades = match previous.1 {
+                    ApertureShape::Circular => 0,
+                    ApertureShape::Polygonal { blades } => blades,
+                };
+
+                ui.add(egui::Slider::new(&mut radius, 0.0..=0.5).text("Aperture"));
+                ui.add(egui::Slider::new(&mut blades, 0..=12).text("Blades (0 is round)"));
+                ui.add(egui::Slider::new(&mut focusDistance, 0.1..=100.0).logarithmic(true).text("Focus distance"));
+
+                let shape = if blades < 3 { ApertureShape::Circular } else { ApertureShape::Polygonal { blades } };
+                if (radius, shape) != (previous.0, previous.1) {
+                    self.camera.setAperture(radius, shape);
+                    self.cameraChanged();
+                }
+                if focusDistance != previous.2 {
+                    self.camera.setFocusDistance(focusDistance);
+                    self.cameraChanged();
+                }
+
+                if ui.button("Autofocus").clicked() {
+                    self.renderThread.autofocus();
+                    self.resetDisplay();
+                }
+            });
+
+            // Projection with its parameter, the lens only applies to perspective and orthographic
+            ui.horizontal(|ui| {
+                let previous = self.camera.projection;
+                let mut projection = previous;
+                egui::ComboBox::from_label("Projection")
+                    .selected_text(projection.name())
+                    .show_ui(ui, |ui| {
+                        for candidate in Projection::ALL {
+                            if ui.selectable_label(projection.key() == candidate.key(), candidate.name()).clicked() {
+                                projection = candidate;
+                            }
+                        }
+                    });
+
+                match &mut projection {
+                    Projection::Orthographic { height } => {
+                        ui.add(egui::Slider::new(height, 0.1..=100.0).logarithmic(true).text("View height"));
+                    }
+                    Projection::FisheyeEquidistant { fov } | Projection::FisheyeEquisolid { fov } => {
+                        ui.add(egui::Slider::new(fov, 10.0..=360.0).text("Fisheye FOV"));
+                    }
+                    _ => {}
+                }
+
+                if projection != previous {
+                    self.camera.setProjection(projection);
+                    self.cameraChanged();
+                }
+            });
+
+            // The linear framebuffer with its AOVs, as multi-layer OpenEXR or PFM files
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut self.savePath);
+                if ui.button("Save").clicked() {
+                    let path = PathBuf::from(self.savePath.trim());
+                    if HasExtension(&path, &["exr", "pfm"]) {
+                        self.renderThread.save(path);
+                        self.saveStatus = String::from("saving...");
+                    } else {
+                        self.saveStatus = String::from("use a .exr or .pfm file name");
+                    }
+                }
+                ui.label(&self.saveStatus);
+            });
+
+            if let Some(ref texture) = self.renderTexture {
+                let img = Image::from_texture(texture);
+                img.paint_at(ui, Rect {
+                    min: egui::pos2(ui.min_rect().min.x, ui.min_rect().min.y),
+                    max: egui::pos2(self.camera.imageWidth, self.camera.imageHeight),
+                });
+            }
+        });
+    }
+}
@@ -0,0 +1,249 @@
+use nalgebra::{Vector2, Vector3};
+use rust_embree::{CommitScene, CreateDevice, CreateScene, CreateSphereGeometry, CreateTriangleGeometry, EmbreeDevice, EmbreeScene};
+
+use crate::intersection::{Intersect, Occluded, SurfaceHit, MAX_PASS_THROUGH};
+use crate::light::{EnvironmentLight, Light, MeshLight, SphereLight};
+use crate::material::Material;
+
+pub const DEFAULT_MATERIAL: usize = 0;
+
+// World space triangle mesh with optional per-vertex attributes
+pub struct TriangleMesh {
+    pub positions: Vec<Vector3<f32>>,
+    pub indices: Vec<(u32, u32, u32)>,
+    pub uvs: Option<Vec<Vector2<f32>>>,
+    pub normals: Option<Vec<Vector3<f32>>>,     // need not be normalized
+}
+
+impl TriangleMesh {
+    pub fn new(positions: Vec<Vector3<f32>>, indices: Vec<(u32, u32, u32)>) -> Self {
+        Self { positions, indices, uvs: None, normals: None }
+    }
+
+    fn triangle(&self, primitiveId: u32) -> Option<(usize, usize, usize)> {
+        self.indices.get(primitiveId as usize).map(|&(a, b, c)| (a as usize, b as usize, c as usize))
+    }
+}
+
+struct Geometry {
+    material: usize,
+    mesh: Option<TriangleMesh>,     // kept for attribute interpolation, None for analytic shapes
+    uvAreaRatios: Vec<f32>,         // per triangle, see SurfaceHit::uvAreaRatio
+    light: Option<usize>,           // area light created for emissive geometry
+}
+
+// Embree scene together with the per-geometry data the integrators need
+pub struct World {
+    device: EmbreeDevice,
+    scene: EmbreeScene,
+    materials: Vec<Material>,
+    geometries: Vec<Geometry>,      // indexed by embree geometry id
+    lights: Vec<Box<dyn Light>>,
+    environment: Option<usize>,     // index into lights
+    hasCutOuts: bool,               // some material lets rays through, see Material::isCutOut
+}
+
+// Embree devices and committed scenes may be used from any thread, and queried concurrently
+unsafe impl Send for World {}
+unsafe impl Sync for World {}
+
+fn InterpolateBarycentric<T>(a: T, b: T, c: T, u: f32, v: f32) -> T
+where
+    T: std::ops::Mul<f32, Output=T> + std::ops::Add<Output=T>,
+{
+    a * (1.0 - u - v) + b * u + c * v
+}
+
+fn ComputeUvAreaRatios(mesh: &TriangleMesh) -> Vec<f32> {
+    let uvs = match &mesh.uvs {
+        Some(uvs) => uvs,
+        None => return vec![],
+    };
+
+    mesh.indices.iter().map(|&(a, b, c)| {
+        let (a, b, c) = (a as usize, b as usize, c as usize);
+        let worldArea = (mesh.positions[b] - mesh.positions[a]).cross(&(mesh.positions[c] - mesh.positions[a])).norm();
+        let uvArea = (uvs[b] - uvs[a]).perp(&(uvs[c] - uvs[a])).abs();
+        0.5 * (uvArea.max(1e-12) / worldArea.max(1e-12)).log2()
+    }).collect()
+}
+
+impl World {
+    pub fn new() -> Self {
+        let device = CreateDevice();
+        let scene = CreateScene(&device);
+
+        Self {
+            device,
+            scene,
+            materials: vec![Material::default()],
+            geometries: vec![],
+            lights: vec![],
+            environment: None,
+            hasCutOuts: false,
+        }
+    }
+
+    pub fn addMaterial(&mut self, material: Material) -> usize {
+        self.hasCutOuts |= material.isCutOut();
+        self.materials.push(material);
+        self.materials.len() - 1
+    }
+
+    pub fn material(&self, geometryId: u32) -> &Material {
+        let index = self.geometries.get(geometryId as usize).map(|geometry| geometry.material).unwrap_or(DEFAULT_MATERIAL);
+        &self.materials[index]
+    }
+
+    fn emission(&self, material: usize) -> Option<Vector3<f32>> {
+        self.materials.get(material).filter(|material| material.isEmissive()).map(|material| material.emission)
+    }
+
+    // Embree hands out geometry ids sequentially in attach order, so the id of the next geometry
+    // is the number of geometries created so far. Meshes with an emissive material also become
+    // area lights.
+    pub fn addTriangleMesh(&mut self, mesh: TriangleMesh, material: usize) -> u32 {
+        let vertices: Vec<(f32, f32, f32)> = mesh.positions.iter().map(|p| (p.x, p.y, p.z)).collect();
+        CreateTriangleGeometry(&self.device, &self.scene, &vertices, &mesh.indices);
+
+        let light = self.emission(material).map(|emission| {
+            let triangles = mesh.indices.iter()
+                .map(|&(a, b, c)| [mesh.positions[a as usize], mesh.positions[b as usize], mesh.positions[c as usize]])
+                .collect();
+            self.addLight(Box::new(MeshLight::new(triangles, emission)))
+        });
+
+        self.geometries.push(Geometry {
+            material,
+            uvAreaRatios: ComputeUvAreaRatios(&mesh),
+            mesh: Some(mesh),
+            light,
+        });
+        (self.geometries.len() - 1) as u32
+    }
+
+    pub fn addSphere(&mut self, center: (f32, f32, f32), radius: f32, material: usize) -> u32 {
+        CreateSphereGeometry(&self.device, &self.scene, center, radius);
+
+        let light = self.emission(material).map(|emission| {
+            let center = Vector3::new(center.0, center.1, center.2);
+            self.addLight(Box::new(SphereLight { center, radius, emission }))
+        });
+
+        self.geometries.push(Geometry { material, mesh: None, uvAreaRatios: vec![], light });
+        (self.geometries.len() - 1) as u32
+    }
+
+    // Lights without geometry, such as point lights. Area lights are added with their geometry.
+    pub fn addLight(&mut self, light: Box<dyn Light>) -> usize {
+        self.lights.push(light);
+        self.lights.len() - 1
+    }
+
+    // Replaces the current environment, which lights rays leaving the scene
+    pub fn setEnvironment(&mut self, environment: EnvironmentLight) {
+        match self.environment {
+            Some(index) => self.lights[index] = Box::new(environment),
+            None => self.environment = Some(self.addLight(Box::new(environment))),
+        }
+    }
+
+    pub fn environment(&self) -> Option<&dyn Light> {
+        self.environment.map(|index| &*self.lights[index])
+    }
+
+    pub fn lights(&self) -> &[Box<dyn Light>] {
+        &self.lights
+    }
+
+    // Area light attached to a geometry, if it is emissive
+    pub fn lightOf(&self, geometryId: u32) -> Option<&dyn Light> {
+        let index = self.geometries.get(geometryId as usize)?.light?;
+        Some(&*self.lights[index])
+    }
+
+    // Lights are picked uniformly
+    pub fn lightPickProbability(&self) -> f32 {
+        if self.lights.is_empty() { 0.0 } else { 1.0 / self.lights.len() as f32 }
+    }
+
+    pub fn sampleLight(&self, u: f32) -> Option<(&dyn Light, f32)> {
+        if self.lights.is_empty() {
+            return None;
+        }
+        let index = ((u * self.lights.len() as f32) as usize).min(self.lights.len() - 1);
+        Some((&*self.lights[index], self.lightPickProbability()))
+    }
+
+    pub fn commit(&self) {
+        CommitScene(&self.scene);
+    }
+
+    // Fraction of light reaching maxDistance along the ray. Cut-out surfaces let 1 - opacity through,
+    // the probability of a path stepping through them, with textures read at their finest level.
+    // Without cut-outs a single occlusion query decides.
+    pub fn transmittance(&self, origin: &Vector3<f32>, direction: &Vector3<f32>, maxDistance: f32) -> f32 {
+        if !self.hasCutOuts {
+            return if Occluded(&self.scene, origin, direction, maxDistance) { 0.0 } else { 1.0 };
+        }
+
+        let mut transmittance = 1.0;
+        let mut origin = *origin;
+        let mut remaining = maxDistance;
+        for _ in 0..=MAX_PASS_THROUGH {
+            let hit = match self.intersect(&origin, direction) {
+                Some(hit) if hit.distance < remaining => hit,
+                _ => return transmittance,
+            };
+            let opacity = self.material(hit.geometryId).opacityAt(&hit, f32::NEG_INFINITY);
+            transmittance *= 1.0 - opacity.clamp(0.0, 1.0);
+            if transmittance <= 0.0 {
+                return 0.0;
+            }
+            origin = hit.spawnOrigin(direction);
+            remaining -= hit.distance;
+        }
+        // Like the path, a surface past the last pass-through is opaque
+        0.0
+    }
+
+    pub fn intersect(&self, origin: &Vector3<f32>, direction: &Vector3<f32>) -> Option<SurfaceHit> {
+        let mut hit = Intersect(&self.scene, origin, direction)?;
+        let geometry = match self.geometries.get(hit.geometryId as usize) {
+            Some(geometry) => geometry,
+            None => return Some(hit),
+        };
+
+        let mesh = match &geometry.mesh {
+            Some(mesh) => mesh,
+            None => return Some(hit),
+        };
+        let (a, b, c) = match mesh.triangle(hit.primitiveId) {
+            Some(triangle) => triangle,
+            None => return Some(hit),
+        };
+
+        if let Some(normals) = &mesh.normals {
+            let normal = InterpolateBarycentric(normals[a], normals[b], normals[c], hit.u, hit.v);
+            if let Some(normal) = normal.try_normalize(1e-12) {
+                // Vertex normals may disagree with the winding, keep them on the side the ray came from
+                hit.shadingNormal = if normal.dot(&hit.geometricNormal) < 0.0 { -normal } else { normal };
+            }
+        }
+
+        if let Some(uvs) = &mesh.uvs {
+            hit.uv = InterpolateBarycentric(uvs[a], uvs[b], uvs[c], hit.u, hit.v);
+            hit.uvAreaRatio = geometry.uvAreaRatios.get(hit.primitiveId as usize).copied();
+
+            // Solve dp = dpdu * du + dpdv * dv over the triangle edges for the tangent dpdu
+            let (edge1, edge2) = (mesh.positions[b] - mesh.positions[a], mesh.positions[c] - mesh.positions[a]);
+            let (duv1, duv2) = (uvs[b] - uvs[a], uvs[c] - uvs[a]);
+            let determinant = duv1.x * duv2.y - duv1.y * duv2.x;
+            if determinant.abs() > 1e-12 {
+                hit.tangent = ((edge1 * duv2.y - edge2 * duv1.y) / determinant).try_normalize(1e-12);
+            }
+        }
+
+        Some(hit)
+    }
+}